@@ -0,0 +1,911 @@
+//! Access control for hosted sites. A site can be limited to signed-in users or to its owner,
+//! and can ask for a password. Owners hand out expiring share links that let anyone in
+//! without an account; visitors who get past a check keep a signed cookie scoped to the site.
+
+use axum::{
+    extract::{Path, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form, Json,
+};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+use crate::auth::{self, AuthUser, Claims};
+use crate::db::{SiteSettings, Visibility};
+use crate::error::AppError;
+use crate::site::{self, SiteHost};
+use crate::AppState;
+
+/// How long an unlocked site stays unlocked in the visitor's browser.
+const ACCESS_TTL_SECS: u64 = 86400;
+
+const DEFAULT_SHARE_HOURS: u64 = 24 * 7;
+
+const MAX_SHARE_HOURS: u64 = 24 * 30;
+
+/// How long a signed-in user opened a restricted site for from the dashboard.
+const VIEW_TTL_SECS: u64 = 3600;
+
+/// Wrong passwords a site accepts per window before it stops checking for a while.
+const MAX_FAILED_UNLOCKS: u32 = 10;
+
+const FAILED_UNLOCK_WINDOW: Duration = Duration::from_secs(60);
+
+/// Password checks running at once. Argon2 is slow on purpose, so unthrottled guessing
+/// would take every core away from serving sites.
+const MAX_PASSWORD_CHECKS: usize = 4;
+
+/// Query parameter a share link carries its token in.
+const SHARE_PARAM: &str = "share_token";
+
+/// Query parameter carrying a signed-in user's identity to a site on another origin.
+const VIEW_PARAM: &str = "view_token";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AccessClaims {
+    site: String,
+    exp: usize,
+}
+
+/// Who is looking at a site: from the session cookie where the site shares a host with the
+/// management UI, otherwise from a viewer token minted by [`open_site`].
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ViewerClaims {
+    site: String,
+    sub: String,
+    is_admin: bool,
+    exp: usize,
+}
+
+#[derive(askama::Template)]
+#[template(path = "site_password.html")]
+struct PasswordTemplate {
+    username: String,
+    error: Option<String>,
+}
+
+#[derive(askama::Template)]
+#[template(path = "site_restricted.html")]
+struct RestrictedTemplate {
+    message: String,
+    show_login: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnlockForm {
+    pub password: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ShareRequest {
+    pub hours: Option<u64>,
+}
+
+/// Throttles password guessing on protected sites. Failures are counted per site, since
+/// visitors' addresses can't be trusted behind a proxy; visitors who already unlocked the
+/// site keep their cookie while it is throttled.
+#[derive(Clone)]
+pub struct UnlockLimiter {
+    failures: Arc<Mutex<HashMap<String, (Instant, u32)>>>,
+    checks: Arc<Semaphore>,
+}
+
+impl Default for UnlockLimiter {
+    fn default() -> Self {
+        Self {
+            failures: Arc::default(),
+            checks: Arc::new(Semaphore::new(MAX_PASSWORD_CHECKS)),
+        }
+    }
+}
+
+impl UnlockLimiter {
+    fn throttled(&self, site: &str) -> bool {
+        self.failures
+            .lock()
+            .unwrap()
+            .get(site)
+            .is_some_and(|(since, count)| {
+                since.elapsed() < FAILED_UNLOCK_WINDOW && *count >= MAX_FAILED_UNLOCKS
+            })
+    }
+
+    fn record_failure(&self, site: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (since, _)| since.elapsed() < FAILED_UNLOCK_WINDOW);
+        failures
+            .entry(site.to_string())
+            .or_insert_with(|| (Instant::now(), 0))
+            .1 += 1;
+    }
+
+    /// Check a password on a blocking thread, a few at a time.
+    async fn verify(&self, password: String, hash: String) -> bool {
+        let Ok(_permit) = self.checks.acquire().await else {
+            return false;
+        };
+        tokio::task::spawn_blocking(move || auth::verify_password(&password, &hash))
+            .await
+            .is_ok_and(|verified| verified.unwrap_or(false))
+    }
+}
+
+pub enum SiteAccess {
+    Public,
+    /// Restricted, but this visitor may see it. Responses must not be shared by caches.
+    Granted,
+    /// Send this instead of the page: a prompt, or a redirect that sets an access cookie.
+    Respond(Response),
+}
+
+/// Secrets the tokens for one site are checked against, looked up before deciding.
+struct AccessKeys<'a> {
+    jwt_secret: &'a str,
+    session_cookie: &'a str,
+    /// Absent until the owner makes their first share link.
+    share_key: Option<Vec<u8>>,
+    /// Present only while the site has a password.
+    password_key: Option<Vec<u8>>,
+}
+
+/// The outcome of an access check, before it is turned into a response.
+#[derive(Debug, PartialEq)]
+enum Decision<'a> {
+    Public,
+    Granted,
+    /// Move a token from the URL into a cookie for the site.
+    Redeem {
+        param: &'static str,
+        cookie_name: String,
+        token: &'a str,
+        exp: usize,
+    },
+    Restricted {
+        message: String,
+        sign_in_helps: bool,
+    },
+    AskPassword,
+}
+
+/// Decide whether the visitor may see `username`'s site. `path` and `query` are those of
+/// the request, for sending a visitor who followed a share link back to the same page.
+pub fn check(
+    state: &AppState,
+    username: &str,
+    settings: &SiteSettings,
+    base: &str,
+    path: &str,
+    query: Option<&str>,
+    headers: &HeaderMap,
+) -> SiteAccess {
+    // Most sites are public and need no lookups
+    if is_public(settings) {
+        return SiteAccess::Public;
+    }
+    let secret = &state.config.jwt_secret;
+    let share_key = match state.db.get_share_key(username) {
+        Ok(key) => key.map(|key| share_signing_key(secret, &key)),
+        Err(e) => return check_failed(e),
+    };
+    let password_key = match state.db.get_site_password(username) {
+        Ok(hash) => hash.map(|hash| password_signing_key(secret, &hash)),
+        Err(e) => return check_failed(e),
+    };
+    let keys = AccessKeys {
+        jwt_secret: secret,
+        session_cookie: auth::session_cookie_name(&state.config),
+        share_key,
+        password_key,
+    };
+
+    match decide(&keys, username, settings, query, headers) {
+        Decision::Public => SiteAccess::Public,
+        Decision::Granted => SiteAccess::Granted,
+        Decision::Redeem {
+            param,
+            cookie_name,
+            token,
+            exp,
+        } => SiteAccess::Respond(redeem(param, &cookie_name, token, exp, base, path, query)),
+        Decision::Restricted {
+            message,
+            sign_in_helps,
+        } => SiteAccess::Respond(restricted(state, &message, base, sign_in_helps)),
+        Decision::AskPassword => SiteAccess::Respond(prompt(username, None)),
+    }
+}
+
+fn is_public(settings: &SiteSettings) -> bool {
+    let listed = matches!(
+        settings.visibility,
+        Visibility::Public | Visibility::Unlisted
+    );
+    listed && !settings.password_protected
+}
+
+fn decide<'a>(
+    keys: &AccessKeys,
+    username: &str,
+    settings: &SiteSettings,
+    query: Option<&'a str>,
+    headers: &HeaderMap,
+) -> Decision<'a> {
+    if is_public(settings) {
+        return Decision::Public;
+    }
+
+    if let Some(token) = query.and_then(|q| query_param(q, VIEW_PARAM)) {
+        return match verify_viewer(keys.jwt_secret, token, username) {
+            Some(claims) => Decision::Redeem {
+                param: VIEW_PARAM,
+                cookie_name: view_cookie_name(username),
+                token,
+                exp: claims.exp,
+            },
+            None => Decision::Restricted {
+                message: "This link has expired. Open the site from your dashboard again."
+                    .to_string(),
+                sign_in_helps: false,
+            },
+        };
+    }
+
+    // Owners and admins always get in, and a share link stands in for an account
+    let viewer = viewer(keys, username, headers);
+    if viewer
+        .as_ref()
+        .is_some_and(|claims| claims.sub == username || claims.is_admin)
+    {
+        return Decision::Granted;
+    }
+    if let Some(token) = query.and_then(|q| query_param(q, SHARE_PARAM)) {
+        let claims = keys
+            .share_key
+            .as_deref()
+            .and_then(|key| verify(token, key, username));
+        return match claims {
+            Some(claims) => Decision::Redeem {
+                param: SHARE_PARAM,
+                cookie_name: share_cookie_name(username),
+                token,
+                exp: claims.exp,
+            },
+            None => Decision::Restricted {
+                message: "This share link has expired or was revoked.".to_string(),
+                sign_in_helps: false,
+            },
+        };
+    }
+    if let Some(key) = &keys.share_key {
+        if cookie(headers, &share_cookie_name(username))
+            .is_some_and(|t| verify(t, key, username).is_some())
+        {
+            return Decision::Granted;
+        }
+    }
+
+    let signed_in = viewer.is_some();
+    match settings.visibility {
+        Visibility::Users if !signed_in => {
+            return Decision::Restricted {
+                message: format!("Sign in to see {username}'s site."),
+                sign_in_helps: true,
+            };
+        }
+        Visibility::Private => {
+            return Decision::Restricted {
+                message: format!("{username}'s site is private."),
+                sign_in_helps: !signed_in,
+            };
+        }
+        _ => {}
+    }
+
+    let Some(key) = &keys.password_key else {
+        return Decision::Granted;
+    };
+    let unlocked = cookie(headers, &password_cookie_name(username))
+        .is_some_and(|token| verify(token, key, username).is_some());
+    if unlocked {
+        Decision::Granted
+    } else {
+        Decision::AskPassword
+    }
+}
+
+/// The password prompt posts back to the page it was shown on.
+pub async fn unlock_site(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    RawQuery(query): RawQuery,
+    site_host: Option<Extension<SiteHost>>,
+    Form(form): Form<UnlockForm>,
+) -> Response {
+    let username = params.get("username").map(String::as_str).unwrap_or("");
+    let hash = match state.db.get_site_password(username) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return crate::error::error_page(StatusCode::NOT_FOUND),
+        Err(e) => return check_failed_page(e),
+    };
+    let limiter = &state.unlock_limiter;
+    if limiter.throttled(username) {
+        let message = "Too many wrong passwords, please try again in a minute";
+        let mut response = prompt(username, Some(message.to_string()));
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        let retry_after = HeaderValue::from(FAILED_UNLOCK_WINDOW.as_secs());
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, retry_after);
+        return response;
+    }
+    if !limiter.verify(form.password, hash.clone()).await {
+        limiter.record_failure(username);
+        return prompt(username, Some("Incorrect password".to_string()));
+    }
+
+    let base = site::site_base(username, site_host.is_some());
+    let claims = AccessClaims {
+        site: username.to_string(),
+        exp: (unix_now() + ACCESS_TTL_SECS) as usize,
+    };
+    let key = password_signing_key(&state.config.jwt_secret, &hash);
+    let Ok(token) = sign(&claims, &key) else {
+        return crate::error::error_page(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let cookie = format!(
+        "{}={token}; Path={base}; HttpOnly; SameSite=Lax; Max-Age={ACCESS_TTL_SECS}",
+        password_cookie_name(username)
+    );
+
+    // Back to the same page, now as a GET
+    let path = params.get("path").map(String::as_str).unwrap_or("");
+    let location = match query {
+        Some(query) => format!("{base}{path}?{query}"),
+        None => format!("{base}{path}"),
+    };
+    (
+        StatusCode::SEE_OTHER,
+        [
+            (header::SET_COOKIE, cookie),
+            (header::LOCATION, site::encode_location(&location)),
+        ],
+    )
+        .into_response()
+}
+
+/// Create a link that lets anyone see the owner's site until it expires.
+pub async fn create_share_link(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(req): Json<ShareRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let hours = req.hours.unwrap_or(DEFAULT_SHARE_HOURS);
+    if !(1..=MAX_SHARE_HOURS).contains(&hours) {
+        return Err(AppError::BadRequest(format!(
+            "Share links last between 1 and {MAX_SHARE_HOURS} hours"
+        )));
+    }
+    let (url, exp) = share_url(&state, &auth.username, hours * 3600)?;
+    let expires_at = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(exp));
+    Ok(Json(
+        serde_json::json!({ "url": url, "expires_at": expires_at }),
+    ))
+}
+
+/// Revoke every share link made so far.
+pub async fn revoke_share_links(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    state.db.set_share_key(&auth.username, &new_share_key())?;
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// Open a site as the signed-in user. Sites on another origin never see the session
+/// cookie, so restricted sites are opened with a short-lived viewer token naming the user.
+/// The site then applies its usual rules to that user, password prompt included.
+pub async fn open_site(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Response, AppError> {
+    let settings = state.db.get_site_settings(&username)?;
+    let site_url = state.config.site_url(&username);
+    let restricted = settings.password_protected
+        || matches!(settings.visibility, Visibility::Users | Visibility::Private);
+    if !restricted {
+        return Ok(Redirect::to(&site_url).into_response());
+    }
+    let claims = ViewerClaims {
+        site: username.clone(),
+        sub: auth.username,
+        is_admin: auth.is_admin,
+        exp: (unix_now() + VIEW_TTL_SECS) as usize,
+    };
+    let token = sign(&claims, &view_signing_key(&state.config.jwt_secret))?;
+    Ok(Redirect::to(&format!("{site_url}?{VIEW_PARAM}={token}")).into_response())
+}
+
+/// A share link for the site and the Unix time it expires at.
+fn share_url(state: &AppState, username: &str, ttl_secs: u64) -> Result<(String, u64), AppError> {
+    let key = match state.db.get_share_key(username)? {
+        Some(key) => key,
+        None => {
+            let key = new_share_key();
+            state.db.set_share_key(username, &key)?;
+            key
+        }
+    };
+    let exp = unix_now() + ttl_secs;
+    let claims = AccessClaims {
+        site: username.to_string(),
+        exp: exp as usize,
+    };
+    let token = sign(&claims, &share_signing_key(&state.config.jwt_secret, &key))?;
+    let url = format!("{}?{SHARE_PARAM}={token}", state.config.site_url(username));
+    Ok((url, exp))
+}
+
+/// Keep a token from the URL in a cookie and send the visitor on without it in the URL.
+fn redeem(
+    param: &str,
+    cookie_name: &str,
+    token: &str,
+    exp: usize,
+    base: &str,
+    path: &str,
+    query: Option<&str>,
+) -> Response {
+    let max_age = (exp as u64).saturating_sub(unix_now());
+    let cookie =
+        format!("{cookie_name}={token}; Path={base}; HttpOnly; SameSite=Lax; Max-Age={max_age}");
+    let rest: Vec<&str> = query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(param))
+        .collect();
+    let location = if rest.is_empty() {
+        format!("{base}{path}")
+    } else {
+        format!("{base}{path}?{}", rest.join("&"))
+    };
+    (
+        StatusCode::SEE_OTHER,
+        [
+            (header::SET_COOKIE, cookie),
+            (header::LOCATION, site::encode_location(&location)),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response()
+}
+
+fn prompt(username: &str, error: Option<String>) -> Response {
+    let template = PasswordTemplate {
+        username: username.to_string(),
+        error,
+    };
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(template.to_string()),
+    )
+        .into_response()
+}
+
+/// The page for visitors the site's visibility keeps out. Signing in only helps where the
+/// site shares a host with the management UI, since elsewhere the session cookie isn't sent.
+fn restricted(state: &AppState, message: &str, base: &str, sign_in_helps: bool) -> Response {
+    let template = RestrictedTemplate {
+        message: message.to_string(),
+        show_login: sign_in_helps && base != "/" && state.config.content_origin.is_none(),
+    };
+    let status = if sign_in_helps {
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::FORBIDDEN
+    };
+    (
+        status,
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(template.to_string()),
+    )
+        .into_response()
+}
+
+/// Access checks fail closed: when the database can't say who may see a site, nobody does.
+fn check_failed(e: impl std::fmt::Display) -> SiteAccess {
+    SiteAccess::Respond(check_failed_page(e))
+}
+
+fn check_failed_page(e: impl std::fmt::Display) -> Response {
+    tracing::error!("Failed to check site access: {e}");
+    crate::error::error_page(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The signed-in user, from the management session cookie or the site's viewer cookie.
+fn viewer(keys: &AccessKeys, username: &str, headers: &HeaderMap) -> Option<ViewerClaims> {
+    let session = cookie(headers, keys.session_cookie)
+        .and_then(|token| auth::decode_token(token, keys.jwt_secret).ok())
+        .map(|claims: Claims| ViewerClaims {
+            site: username.to_string(),
+            sub: claims.sub,
+            is_admin: claims.is_admin,
+            exp: claims.exp,
+        });
+    session.or_else(|| {
+        let token = cookie(headers, &view_cookie_name(username))?;
+        verify_viewer(keys.jwt_secret, token, username)
+    })
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| c.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn password_cookie_name(username: &str) -> String {
+    format!("site_access_{username}")
+}
+
+fn share_cookie_name(username: &str) -> String {
+    format!("site_share_{username}")
+}
+
+fn view_cookie_name(username: &str) -> String {
+    format!("site_view_{username}")
+}
+
+/// Signed with the password hash mixed into the key, so changing or clearing the password
+/// revokes every cookie handed out for the old one.
+fn password_signing_key(jwt_secret: &str, hash: &str) -> Vec<u8> {
+    format!("{jwt_secret}\n{hash}").into_bytes()
+}
+
+fn share_signing_key(jwt_secret: &str, share_key: &str) -> Vec<u8> {
+    format!("{jwt_secret}\nshare\n{share_key}").into_bytes()
+}
+
+fn view_signing_key(jwt_secret: &str) -> Vec<u8> {
+    format!("{jwt_secret}\nview").into_bytes()
+}
+
+fn new_share_key() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+fn sign<T: serde::Serialize>(
+    claims: &T,
+    key: &[u8],
+) -> Result<String, jsonwebtoken::errors::Error> {
+    encode(&Header::default(), claims, &EncodingKey::from_secret(key))
+}
+
+fn verify(token: &str, key: &[u8], username: &str) -> Option<AccessClaims> {
+    decode::<AccessClaims>(
+        token,
+        &DecodingKey::from_secret(key),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+    .filter(|claims| claims.site == username)
+}
+
+fn verify_viewer(jwt_secret: &str, token: &str, username: &str) -> Option<ViewerClaims> {
+    decode::<ViewerClaims>(
+        token,
+        &DecodingKey::from_secret(&view_signing_key(jwt_secret)),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+    .filter(|claims| claims.site == username)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+    const SESSION: &str = "token";
+    const SITE: &str = "alice";
+
+    struct Site {
+        settings: SiteSettings,
+        share_key: Option<&'static str>,
+        password_hash: Option<&'static str>,
+    }
+
+    impl Site {
+        fn new(visibility: Visibility) -> Self {
+            Self {
+                settings: SiteSettings {
+                    visibility,
+                    ..SiteSettings::default()
+                },
+                share_key: Some("share-key"),
+                password_hash: None,
+            }
+        }
+
+        fn with_password(mut self) -> Self {
+            self.settings.password_protected = true;
+            self.password_hash = Some("$argon2id$hash");
+            self
+        }
+
+        fn decide<'a>(&self, query: Option<&'a str>, cookies: &[(String, String)]) -> Decision<'a> {
+            let keys = AccessKeys {
+                jwt_secret: SECRET,
+                session_cookie: SESSION,
+                share_key: self.share_key.map(|key| share_signing_key(SECRET, key)),
+                password_key: self
+                    .password_hash
+                    .map(|hash| password_signing_key(SECRET, hash)),
+            };
+            let mut headers = HeaderMap::new();
+            let cookie: Vec<String> = cookies
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            headers.insert(header::COOKIE, cookie.join("; ").parse().unwrap());
+            decide(&keys, SITE, &self.settings, query, &headers)
+        }
+    }
+
+    fn session(username: &str, is_admin: bool) -> (String, String) {
+        let token = auth::create_token(username, is_admin, SECRET).unwrap();
+        (SESSION.to_string(), token)
+    }
+
+    fn access_token(site: &str, key: &[u8], ttl_secs: i64) -> String {
+        let claims = AccessClaims {
+            site: site.to_string(),
+            exp: (unix_now() as i64 + ttl_secs) as usize,
+        };
+        sign(&claims, key).unwrap()
+    }
+
+    fn share_token(site: &str, share_key: &str, ttl_secs: i64) -> String {
+        access_token(site, &share_signing_key(SECRET, share_key), ttl_secs)
+    }
+
+    fn viewer_token(site: &str, username: &str, ttl_secs: i64) -> String {
+        let claims = ViewerClaims {
+            site: site.to_string(),
+            sub: username.to_string(),
+            is_admin: false,
+            exp: (unix_now() as i64 + ttl_secs) as usize,
+        };
+        sign(&claims, &view_signing_key(SECRET)).unwrap()
+    }
+
+    fn is_restricted(decision: &Decision, sign_in_helps: bool) -> bool {
+        matches!(
+            decision,
+            Decision::Restricted { sign_in_helps: helps, .. } if *helps == sign_in_helps
+        )
+    }
+
+    #[test]
+    fn public_and_unlisted_sites_are_open() {
+        for visibility in [Visibility::Public, Visibility::Unlisted] {
+            assert_eq!(Site::new(visibility).decide(None, &[]), Decision::Public);
+        }
+    }
+
+    #[test]
+    fn users_sites_need_any_signed_in_user() {
+        let site = Site::new(Visibility::Users);
+        assert!(is_restricted(&site.decide(None, &[]), true));
+        assert_eq!(
+            site.decide(None, &[session("bob", false)]),
+            Decision::Granted
+        );
+
+        let cookies = [(view_cookie_name(SITE), viewer_token(SITE, "bob", 3600))];
+        assert_eq!(site.decide(None, &cookies), Decision::Granted);
+    }
+
+    #[test]
+    fn private_sites_are_for_the_owner_and_admins() {
+        let site = Site::new(Visibility::Private);
+        assert!(is_restricted(&site.decide(None, &[]), true));
+        assert!(is_restricted(
+            &site.decide(None, &[session("bob", false)]),
+            false
+        ));
+        assert_eq!(
+            site.decide(None, &[session(SITE, false)]),
+            Decision::Granted
+        );
+        assert_eq!(
+            site.decide(None, &[session("root", true)]),
+            Decision::Granted
+        );
+    }
+
+    #[test]
+    fn owner_and_admin_skip_the_password() {
+        let site = Site::new(Visibility::Public).with_password();
+        assert_eq!(site.decide(None, &[]), Decision::AskPassword);
+        assert_eq!(
+            site.decide(None, &[session(SITE, false)]),
+            Decision::Granted
+        );
+        assert_eq!(
+            site.decide(None, &[session("root", true)]),
+            Decision::Granted
+        );
+    }
+
+    #[test]
+    fn a_session_from_another_secret_is_ignored() {
+        let site = Site::new(Visibility::Private);
+        let forged = auth::create_token(SITE, true, "other-secret").unwrap();
+        assert!(is_restricted(
+            &site.decide(None, &[(SESSION.to_string(), forged)]),
+            true
+        ));
+    }
+
+    #[test]
+    fn share_links_are_redeemed_into_a_cookie() {
+        let site = Site::new(Visibility::Private);
+        let token = share_token(SITE, "share-key", 3600);
+        let query = format!("{SHARE_PARAM}={token}&page=2");
+        match site.decide(Some(&query), &[]) {
+            Decision::Redeem {
+                param,
+                cookie_name,
+                token: redeemed,
+                ..
+            } => {
+                assert_eq!(param, SHARE_PARAM);
+                assert_eq!(cookie_name, share_cookie_name(SITE));
+                assert_eq!(redeemed, token);
+            }
+            other => panic!("expected a redeem, got {other:?}"),
+        }
+
+        let cookies = [(share_cookie_name(SITE), token)];
+        assert_eq!(site.decide(None, &cookies), Decision::Granted);
+    }
+
+    #[test]
+    fn expired_share_links_are_refused() {
+        let site = Site::new(Visibility::Private);
+        let token = share_token(SITE, "share-key", -3600);
+        let query = format!("{SHARE_PARAM}={token}");
+        assert!(is_restricted(&site.decide(Some(&query), &[]), false));
+
+        let cookies = [(share_cookie_name(SITE), token)];
+        assert!(is_restricted(&site.decide(None, &cookies), true));
+    }
+
+    #[test]
+    fn revoked_share_links_are_refused() {
+        // Revoking replaces the share key, so tokens signed with the old one stop verifying
+        let site = Site::new(Visibility::Private);
+        let token = share_token(SITE, "old-share-key", 3600);
+        let query = format!("{SHARE_PARAM}={token}");
+        assert!(is_restricted(&site.decide(Some(&query), &[]), false));
+
+        let cookies = [(share_cookie_name(SITE), token)];
+        assert!(is_restricted(&site.decide(None, &cookies), true));
+
+        let never_shared = Site {
+            share_key: None,
+            ..Site::new(Visibility::Private)
+        };
+        let query = format!("{SHARE_PARAM}={}", share_token(SITE, "share-key", 3600));
+        assert!(is_restricted(
+            &never_shared.decide(Some(&query), &[]),
+            false
+        ));
+    }
+
+    #[test]
+    fn share_links_for_another_site_are_refused() {
+        let site = Site::new(Visibility::Private);
+        let query = format!("{SHARE_PARAM}={}", share_token("bob", "share-key", 3600));
+        assert!(is_restricted(&site.decide(Some(&query), &[]), false));
+    }
+
+    #[test]
+    fn viewer_tokens_are_redeemed_for_their_own_site_only() {
+        let site = Site::new(Visibility::Users);
+        let query = format!("{VIEW_PARAM}={}", viewer_token(SITE, "bob", 3600));
+        assert!(matches!(
+            site.decide(Some(&query), &[]),
+            Decision::Redeem {
+                param: VIEW_PARAM,
+                ..
+            }
+        ));
+
+        let query = format!("{VIEW_PARAM}={}", viewer_token("carol", "bob", 3600));
+        assert!(is_restricted(&site.decide(Some(&query), &[]), false));
+
+        let query = format!("{VIEW_PARAM}={}", viewer_token(SITE, "bob", -3600));
+        assert!(is_restricted(&site.decide(Some(&query), &[]), false));
+    }
+
+    #[test]
+    fn viewer_cookies_for_another_site_are_ignored() {
+        let site = Site::new(Visibility::Users);
+        let cookies = [(view_cookie_name(SITE), viewer_token("carol", "bob", 3600))];
+        assert!(is_restricted(&site.decide(None, &cookies), true));
+    }
+
+    #[test]
+    fn password_applies_on_top_of_users() {
+        let site = Site::new(Visibility::Users).with_password();
+        assert!(is_restricted(&site.decide(None, &[]), true));
+        assert_eq!(
+            site.decide(None, &[session("bob", false)]),
+            Decision::AskPassword
+        );
+
+        let key = password_signing_key(SECRET, "$argon2id$hash");
+        let unlocked = (password_cookie_name(SITE), access_token(SITE, &key, 3600));
+        assert_eq!(
+            site.decide(None, &[session("bob", false), unlocked.clone()]),
+            Decision::Granted
+        );
+        // The password alone doesn't stand in for signing in
+        assert!(is_restricted(&site.decide(None, &[unlocked]), true));
+    }
+
+    #[test]
+    fn password_cookies_are_revoked_with_the_password() {
+        let site = Site::new(Visibility::Public).with_password();
+
+        let old_key = password_signing_key(SECRET, "$argon2id$old-hash");
+        let stale = [(
+            password_cookie_name(SITE),
+            access_token(SITE, &old_key, 3600),
+        )];
+        assert_eq!(site.decide(None, &stale), Decision::AskPassword);
+
+        let key = password_signing_key(SECRET, "$argon2id$hash");
+        let other_site = [(password_cookie_name(SITE), access_token("bob", &key, 3600))];
+        assert_eq!(site.decide(None, &other_site), Decision::AskPassword);
+        let expired = [(password_cookie_name(SITE), access_token(SITE, &key, -3600))];
+        assert_eq!(site.decide(None, &expired), Decision::AskPassword);
+        let valid = [(password_cookie_name(SITE), access_token(SITE, &key, 3600))];
+        assert_eq!(site.decide(None, &valid), Decision::Granted);
+    }
+
+    #[test]
+    fn password_signing_key_depends_on_secret_and_hash() {
+        let key = password_signing_key(SECRET, "hash");
+        assert_ne!(key, password_signing_key(SECRET, "other-hash"));
+        assert_ne!(key, password_signing_key("other-secret", "hash"));
+        // Never the bare session secret, so a session token can't pass for an unlock cookie
+        assert_ne!(key, SECRET.as_bytes());
+        assert_ne!(key, share_signing_key(SECRET, "hash"));
+    }
+}
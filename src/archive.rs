@@ -0,0 +1,733 @@
+use std::io::{BufReader, Read, Seek, Write};
+use std::path::{Path as StdPath, PathBuf};
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// Archive formats accepted for upload, recognised by their leading magic bytes
+/// rather than by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    SevenZ,
+    Tar,
+    TarGz,
+    TarBz2,
+    TarXz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn detect(archive_path: &StdPath) -> Result<Self, AppError> {
+        let file = std::fs::File::open(archive_path)?;
+        let mut header = Vec::with_capacity(512);
+        file.take(512).read_to_end(&mut header)?;
+
+        let format = if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Self::Zip
+        } else if header.starts_with(&[0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C]) {
+            Self::SevenZ
+        } else if header.starts_with(&[0x1F, 0x8B]) {
+            Self::TarGz
+        } else if header.starts_with(b"BZh") {
+            Self::TarBz2
+        } else if header.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::TarXz
+        } else if header.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Self::TarZst
+        } else if header.get(257..262) == Some(b"ustar") {
+            Self::Tar
+        } else {
+            return Err(AppError::BadRequest(
+                "Unsupported format. Please upload a .zip, .7z or .tar archive \
+                 (optionally compressed with gzip, bzip2, xz or zstd)"
+                    .to_string(),
+            ));
+        };
+        Ok(format)
+    }
+}
+
+/// File extensions of the formats above, used only to give a clear error when a file
+/// named like an archive turns out not to be one.
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    ".zip", ".7z", ".tar", ".tgz", ".gz", ".tbz2", ".bz2", ".txz", ".xz", ".tzst", ".zst",
+];
+
+pub fn has_archive_extension(filename: &str) -> bool {
+    let lower = filename.to_lowercase();
+    ARCHIVE_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+}
+
+/// Extract an uploaded archive into `dest`, flattening a single top-level folder.
+pub fn extract(
+    archive_path: &StdPath,
+    format: ArchiveFormat,
+    dest: &StdPath,
+    limits: &mut ExtractLimits,
+) -> Result<(), AppError> {
+    let reader = BufReader::new(std::fs::File::open(archive_path)?);
+
+    match format {
+        ArchiveFormat::Zip => extract_zip(reader, dest, limits)?,
+        ArchiveFormat::SevenZ => extract_7z(archive_path, dest, limits)?,
+        ArchiveFormat::Tar => extract_tar(reader, dest, limits)?,
+        ArchiveFormat::TarGz => extract_tar(flate2::read::GzDecoder::new(reader), dest, limits)?,
+        ArchiveFormat::TarBz2 => extract_tar(bzip2::read::BzDecoder::new(reader), dest, limits)?,
+        ArchiveFormat::TarXz => extract_tar(xz2::read::XzDecoder::new(reader), dest, limits)?,
+        ArchiveFormat::TarZst => {
+            let zst = zstd::stream::read::Decoder::with_buffer(reader)
+                .map_err(|e| AppError::BadRequest(format!("Invalid zstd stream: {e}")))?;
+            extract_tar(zst, dest, limits)?
+        }
+    }
+
+    // If all files share a common top-level directory, flatten it
+    flatten_single_root(dest)?;
+
+    Ok(())
+}
+
+/// Place individually uploaded files into `dest` by their relative paths, with the same
+/// path rules and limits as archive entries. A lone HTML file becomes the site's index.
+pub fn stage_files(
+    files: &[(String, &StdPath)],
+    dest: &StdPath,
+    limits: &mut ExtractLimits,
+) -> Result<(), AppError> {
+    for (name, source) in files {
+        // Windows browsers may report folder uploads with backslashes
+        let mut entry_path = sanitize_archive_path(&name.replace('\\', "/"))?;
+        if entry_path == StdPath::new(".") {
+            return Err(AppError::BadRequest("Uploaded file has no name".to_string()));
+        }
+
+        let is_html = entry_path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("html") || e.eq_ignore_ascii_case("htm"));
+        if files.len() == 1 && is_html {
+            entry_path = PathBuf::from("index.html");
+        }
+
+        limits.check_entry(&entry_path)?;
+        let mut reader = std::fs::File::open(source)?;
+        write_entry(dest, &entry_path, &mut reader, limits)?;
+    }
+
+    // Folder pickers prefix every path with the chosen folder's name
+    flatten_single_root(dest)?;
+
+    Ok(())
+}
+
+/// Running totals checked while an archive is extracted, so oversized or hostile
+/// archives are rejected before they are fully written to disk.
+pub struct ExtractLimits {
+    quota_bytes: u64,
+    max_entries: u64,
+    max_depth: usize,
+    max_path_length: usize,
+    max_ratio: u64,
+    archive_size: u64,
+    entries: u64,
+    bytes: u64,
+}
+
+/// Compression ratio is only enforced past this many extracted bytes, so small
+/// archives of very repetitive text are not rejected.
+const RATIO_GRACE_BYTES: u64 = 1024 * 1024;
+
+impl ExtractLimits {
+    pub fn new(config: &Config, archive_size: u64) -> Self {
+        Self {
+            quota_bytes: config.disk_quota_bytes,
+            max_entries: config.max_archive_entries,
+            max_depth: config.max_path_depth,
+            max_path_length: config.max_path_length,
+            max_ratio: config.max_compression_ratio,
+            archive_size,
+            entries: 0,
+            bytes: 0,
+        }
+    }
+
+    fn check_entry(&mut self, path: &StdPath) -> Result<(), AppError> {
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            return Err(AppError::PayloadTooLarge(format!(
+                "Archive contains more than {} files and folders",
+                self.max_entries
+            )));
+        }
+
+        check_path_shape(path, self.max_depth, self.max_path_length)
+    }
+
+    /// Copy one entry to disk, aborting as soon as a size limit is crossed.
+    fn copy(
+        &mut self,
+        reader: &mut (impl Read + ?Sized),
+        writer: &mut impl Write,
+    ) -> Result<(), AppError> {
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = reader
+                .read(&mut buf)
+                .map_err(|e| AppError::BadRequest(format!("Failed to read archive entry: {e}")))?;
+            if n == 0 {
+                return Ok(());
+            }
+
+            self.bytes += n as u64;
+            if self.bytes > self.quota_bytes {
+                return Err(AppError::PayloadTooLarge(format!(
+                    "Extracted files exceed disk quota of {} MB",
+                    self.quota_bytes / (1024 * 1024)
+                )));
+            }
+            if self.bytes > RATIO_GRACE_BYTES
+                && self.bytes > self.archive_size.saturating_mul(self.max_ratio)
+            {
+                return Err(AppError::PayloadTooLarge(format!(
+                    "Archive expands to more than {}x its compressed size",
+                    self.max_ratio
+                )));
+            }
+
+            writer.write_all(&buf[..n])?;
+        }
+    }
+}
+
+fn extract_zip(
+    reader: impl Read + Seek,
+    dest: &StdPath,
+    limits: &mut ExtractLimits,
+) -> Result<(), AppError> {
+    let mut archive = zip::ZipArchive::new(reader)
+        .map_err(|e| AppError::BadRequest(format!("Invalid zip file: {e}")))?;
+
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| AppError::BadRequest(format!("Failed to read zip entry: {e}")))?;
+
+        let raw_name = file.name().to_string();
+        let entry_path = sanitize_archive_path(&raw_name)?;
+        limits.check_entry(&entry_path)?;
+
+        if file.is_dir() {
+            std::fs::create_dir_all(dest.join(&entry_path))?;
+            continue;
+        }
+
+        write_entry(dest, &entry_path, &mut file, limits)?;
+    }
+
+    Ok(())
+}
+
+fn extract_tar(
+    reader: impl Read,
+    dest: &StdPath,
+    limits: &mut ExtractLimits,
+) -> Result<(), AppError> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive
+        .entries()
+        .map_err(|e| AppError::BadRequest(format!("Invalid tar archive: {e}")))?
+    {
+        let mut entry =
+            entry.map_err(|e| AppError::BadRequest(format!("Failed to read tar entry: {e}")))?;
+
+        let raw_path = entry
+            .path()
+            .map_err(|e| AppError::BadRequest(format!("Invalid path in archive: {e}")))?
+            .to_string_lossy()
+            .to_string();
+
+        let entry_path = sanitize_archive_path(&raw_path)?;
+        limits.check_entry(&entry_path)?;
+
+        if entry.header().entry_type().is_dir() {
+            std::fs::create_dir_all(dest.join(&entry_path))?;
+        } else if entry.header().entry_type().is_file() {
+            write_entry(dest, &entry_path, &mut entry, limits)?;
+        }
+        // Skip symlinks and other special entries
+    }
+
+    Ok(())
+}
+
+fn extract_7z(
+    archive_path: &StdPath,
+    dest: &StdPath,
+    limits: &mut ExtractLimits,
+) -> Result<(), AppError> {
+    let mut archive = sevenz_rust::SevenZReader::open(archive_path, sevenz_rust::Password::empty())
+        .map_err(|e| AppError::BadRequest(format!("Invalid 7z file: {e}")))?;
+
+    // The callback can only return the library's error type, so keep ours aside
+    let mut failure: Option<AppError> = None;
+    let result = archive.for_each_entries(|entry, reader| {
+        let outcome = sanitize_archive_path(entry.name()).and_then(|entry_path| {
+            limits.check_entry(&entry_path)?;
+            if entry.is_directory() {
+                std::fs::create_dir_all(dest.join(&entry_path))?;
+                Ok(())
+            } else if entry.is_anti_item() {
+                Ok(())
+            } else {
+                write_entry(dest, &entry_path, reader, limits)
+            }
+        });
+        match outcome {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                failure = Some(e);
+                Err(sevenz_rust::Error::other("extraction aborted"))
+            }
+        }
+    });
+
+    match (failure, result) {
+        (Some(e), _) => Err(e),
+        (None, Err(e)) => Err(AppError::BadRequest(format!(
+            "Failed to read 7z entry: {e}"
+        ))),
+        (None, Ok(())) => Ok(()),
+    }
+}
+
+fn write_entry(
+    dest: &StdPath,
+    entry_path: &StdPath,
+    reader: &mut (impl Read + ?Sized),
+    limits: &mut ExtractLimits,
+) -> Result<(), AppError> {
+    let out_path = dest.join(entry_path);
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut out_file = std::fs::File::create(&out_path)?;
+    limits.copy(reader, &mut out_file)
+}
+
+/// Reject paths nested too deeply or too long to be reasonable site content.
+pub fn check_path_shape(path: &StdPath, max_depth: usize, max_length: usize) -> Result<(), AppError> {
+    let display = path.to_string_lossy();
+    if path.components().count() > max_depth {
+        return Err(AppError::BadRequest(format!(
+            "Path is nested more than {max_depth} folders deep: {display}"
+        )));
+    }
+    // Filesystems limit names in bytes, and non-Latin letters take several bytes each
+    if display.len() > max_length {
+        return Err(AppError::BadRequest(format!(
+            "Path is longer than {max_length} bytes: {display}"
+        )));
+    }
+    Ok(())
+}
+
+pub fn sanitize_archive_path(raw: &str) -> Result<PathBuf, AppError> {
+    let path = StdPath::new(raw);
+
+    // Reject absolute paths
+    if path.has_root() {
+        return Err(AppError::BadRequest(format!(
+            "Absolute paths are not allowed: {raw}"
+        )));
+    }
+
+    // Build sanitized path, rejecting .. and dotfiles
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(c) => {
+                let s = c.to_string_lossy();
+                if s.starts_with('.') && s != "." {
+                    return Err(AppError::BadRequest(format!(
+                        "Hidden files are not allowed: {raw}"
+                    )));
+                }
+                result.push(c);
+            }
+            std::path::Component::ParentDir => {
+                return Err(AppError::BadRequest(format!(
+                    "Paths may not contain ..: {raw}"
+                )));
+            }
+            _ => {} // skip CurDir, Prefix, RootDir
+        }
+    }
+
+    if result.as_os_str().is_empty() {
+        result.push(".");
+    }
+
+    Ok(result)
+}
+
+/// If all extracted contents sit inside a single top-level directory, move them up.
+fn flatten_single_root(dir: &StdPath) -> Result<(), AppError> {
+    let entries: Vec<_> = std::fs::read_dir(dir)?.filter_map(|e| e.ok()).collect();
+
+    if entries.len() == 1 && entries[0].file_type().map(|t| t.is_dir()).unwrap_or(false) {
+        let single_dir = entries[0].path();
+        let temp_name = dir.join("__flatten_temp__");
+        std::fs::rename(&single_dir, &temp_name)?;
+
+        // Move all contents from the single dir up to parent
+        for entry in std::fs::read_dir(&temp_name)? {
+            let entry = entry?;
+            std::fs::rename(entry.path(), dir.join(entry.file_name()))?;
+        }
+        std::fs::remove_dir(&temp_name)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zip::write::SimpleFileOptions;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn limits_for(quota_bytes: u64, archive_size: u64) -> ExtractLimits {
+        ExtractLimits {
+            quota_bytes,
+            max_entries: 100,
+            max_depth: 8,
+            max_path_length: 64,
+            max_ratio: 100,
+            archive_size,
+            entries: 0,
+            bytes: 0,
+        }
+    }
+
+    fn zip_archive(files: &[(&str, &[u8])], method: zip::CompressionMethod) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(method);
+        for (name, data) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn tar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, *data).unwrap();
+        }
+        tar.into_inner().unwrap()
+    }
+
+    /// Write `bytes` into a fresh temp dir and extract it into a sibling folder.
+    fn extract_bytes(
+        bytes: &[u8],
+        format: ArchiveFormat,
+        limits: &mut ExtractLimits,
+    ) -> (tempfile::TempDir, Result<(), AppError>) {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("upload");
+        std::fs::write(&archive, bytes).unwrap();
+        let dest = dir.path().join("site");
+        std::fs::create_dir(&dest).unwrap();
+        let result = extract(&archive, format, &dest, limits);
+        (dir, result)
+    }
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|_| rand::random::<u8>()).collect()
+    }
+
+    #[test]
+    fn extracts_a_small_zip() {
+        let bytes = zip_archive(
+            &[("index.html", b"<h1>hi</h1>"), ("css/site.css", b"body {}")],
+            zip::CompressionMethod::Deflated,
+        );
+        let mut limits = limits_for(MB, bytes.len() as u64);
+        let (dir, result) = extract_bytes(&bytes, ArchiveFormat::Zip, &mut limits);
+        result.unwrap();
+        let site = dir.path().join("site");
+        assert_eq!(
+            std::fs::read(site.join("index.html")).unwrap(),
+            b"<h1>hi</h1>"
+        );
+        assert_eq!(
+            std::fs::read(site.join("css/site.css")).unwrap(),
+            b"body {}"
+        );
+    }
+
+    #[test]
+    fn quota_stops_extraction_in_the_middle_of_an_entry() {
+        let data = random_bytes(512 * 1024);
+        let bytes = zip_archive(&[("big.bin", &data)], zip::CompressionMethod::Stored);
+        let quota = 200 * 1024;
+        let mut limits = limits_for(quota, bytes.len() as u64);
+        let (dir, result) = extract_bytes(&bytes, ArchiveFormat::Zip, &mut limits);
+
+        assert!(matches!(result, Err(AppError::PayloadTooLarge(_))));
+        let written = std::fs::metadata(dir.path().join("site/big.bin"))
+            .unwrap()
+            .len();
+        assert!(written > 0 && written <= quota, "wrote {written} bytes");
+    }
+
+    #[test]
+    fn quota_counts_bytes_across_entries() {
+        let data = random_bytes(150 * 1024);
+        let bytes = tar_archive(&[("a.bin", &data), ("b.bin", &data)]);
+        let mut limits = limits_for(200 * 1024, bytes.len() as u64);
+        let (_dir, result) = extract_bytes(&bytes, ArchiveFormat::Tar, &mut limits);
+        assert!(matches!(result, Err(AppError::PayloadTooLarge(_))));
+    }
+
+    #[test]
+    fn compression_ratio_is_limited() {
+        let zeros = vec![0u8; 4 * MB as usize];
+        let bytes = zip_archive(&[("zeros.txt", &zeros)], zip::CompressionMethod::Deflated);
+        let mut limits = limits_for(100 * MB, bytes.len() as u64);
+        let (_dir, result) = extract_bytes(&bytes, ArchiveFormat::Zip, &mut limits);
+        match result {
+            Err(AppError::PayloadTooLarge(msg)) => assert!(msg.contains("100x"), "{msg}"),
+            other => panic!("expected a ratio error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn compression_ratio_allows_the_first_megabyte() {
+        let zeros = vec![0u8; MB as usize];
+        let bytes = zip_archive(&[("zeros.txt", &zeros)], zip::CompressionMethod::Deflated);
+        assert!(bytes.len() as u64 * 100 < MB, "archive didn't compress");
+        let mut limits = limits_for(100 * MB, bytes.len() as u64);
+        let (dir, result) = extract_bytes(&bytes, ArchiveFormat::Zip, &mut limits);
+        result.unwrap();
+        let written = std::fs::metadata(dir.path().join("site/zeros.txt"))
+            .unwrap()
+            .len();
+        assert_eq!(written, MB);
+    }
+
+    #[test]
+    fn entry_count_is_limited() {
+        let files: Vec<(String, &[u8])> = (0..4)
+            .map(|i| (format!("page{i}.html"), &b"x"[..]))
+            .collect();
+        let files: Vec<(&str, &[u8])> = files.iter().map(|(n, d)| (n.as_str(), *d)).collect();
+        let bytes = tar_archive(&files);
+        let mut limits = ExtractLimits {
+            max_entries: 3,
+            ..limits_for(MB, bytes.len() as u64)
+        };
+        let (_dir, result) = extract_bytes(&bytes, ArchiveFormat::Tar, &mut limits);
+        match result {
+            Err(AppError::PayloadTooLarge(msg)) => assert!(msg.contains("more than 3"), "{msg}"),
+            other => panic!("expected an entry count error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        let bytes = tar_archive(&[("a/b/c/d/page.html", b"x")]);
+        let mut limits = ExtractLimits {
+            max_depth: 4,
+            ..limits_for(MB, bytes.len() as u64)
+        };
+        let (_dir, result) = extract_bytes(&bytes, ArchiveFormat::Tar, &mut limits);
+        assert!(matches!(result, Err(AppError::BadRequest(msg)) if msg.contains("4 folders")));
+
+        let mut limits = ExtractLimits {
+            max_depth: 5,
+            ..limits_for(MB, bytes.len() as u64)
+        };
+        extract_bytes(&bytes, ArchiveFormat::Tar, &mut limits)
+            .1
+            .unwrap();
+    }
+
+    #[test]
+    fn path_length_is_counted_in_bytes() {
+        // 20 Thai letters are 20 characters but 60 bytes
+        let name = format!("{}.html", "ก".repeat(20));
+        assert!(check_path_shape(StdPath::new(&name), 8, 64).is_err());
+        assert!(check_path_shape(StdPath::new(&name), 8, 65).is_ok());
+
+        let bytes = zip_archive(&[(&name, b"x")], zip::CompressionMethod::Stored);
+        let mut limits = limits_for(MB, bytes.len() as u64);
+        let (_dir, result) = extract_bytes(&bytes, ArchiveFormat::Zip, &mut limits);
+        assert!(matches!(result, Err(AppError::BadRequest(msg)) if msg.contains("64 bytes")));
+    }
+
+    fn detect_bytes(bytes: &[u8]) -> Result<ArchiveFormat, AppError> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), bytes).unwrap();
+        ArchiveFormat::detect(file.path())
+    }
+
+    fn compressed_tars() -> Vec<(ArchiveFormat, Vec<u8>)> {
+        let tar = tar_archive(&[("site/index.html", b"<h1>hi</h1>")]);
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz2.write_all(&tar).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar).unwrap();
+        vec![
+            (ArchiveFormat::TarGz, gz.finish().unwrap()),
+            (ArchiveFormat::TarBz2, bz2.finish().unwrap()),
+            (ArchiveFormat::TarXz, xz.finish().unwrap()),
+            (
+                ArchiveFormat::TarZst,
+                zstd::encode_all(&tar[..], 0).unwrap(),
+            ),
+            (ArchiveFormat::Tar, tar),
+        ]
+    }
+
+    #[test]
+    fn detect_recognises_zip() {
+        let bytes = zip_archive(&[("index.html", b"x")], zip::CompressionMethod::Deflated);
+        assert_eq!(detect_bytes(&bytes).unwrap(), ArchiveFormat::Zip);
+        // An empty zip is only an end-of-central-directory record
+        let empty = zip::ZipWriter::new(Cursor::new(Vec::new()))
+            .finish()
+            .unwrap();
+        assert_eq!(
+            detect_bytes(&empty.into_inner()).unwrap(),
+            ArchiveFormat::Zip
+        );
+    }
+
+    #[test]
+    fn detect_recognises_7z() {
+        let header = [0x37, 0x7A, 0xBC, 0xAF, 0x27, 0x1C, 0x00, 0x04];
+        assert_eq!(detect_bytes(&header).unwrap(), ArchiveFormat::SevenZ);
+    }
+
+    #[test]
+    fn detect_recognises_tar_and_compressed_tar() {
+        for (format, bytes) in compressed_tars() {
+            assert_eq!(detect_bytes(&bytes).unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn compressed_tars_extract_and_flatten() {
+        for (format, bytes) in compressed_tars() {
+            let mut limits = limits_for(MB, bytes.len() as u64);
+            let (dir, result) = extract_bytes(&bytes, format, &mut limits);
+            result.unwrap();
+            let index = dir.path().join("site/index.html");
+            assert_eq!(std::fs::read(index).unwrap(), b"<h1>hi</h1>", "{format:?}");
+        }
+    }
+
+    #[test]
+    fn detect_rejects_plain_files() {
+        assert!(detect_bytes(b"<!doctype html><h1>hi</h1>").is_err());
+        assert!(detect_bytes(b"").is_err());
+        // "PK" alone is not a zip header
+        assert!(detect_bytes(b"PK is a fine way to start a text file").is_err());
+    }
+
+    #[test]
+    fn archive_extensions_are_case_insensitive() {
+        for name in [
+            "site.zip",
+            "SITE.ZIP",
+            "site.7z",
+            "site.tar",
+            "site.tar.gz",
+            "site.tgz",
+        ] {
+            assert!(has_archive_extension(name), "{name}");
+        }
+        for name in [
+            "site.tar.bz2",
+            "site.tbz2",
+            "site.tar.xz",
+            "site.txz",
+            "site.tar.zst",
+        ] {
+            assert!(has_archive_extension(name), "{name}");
+        }
+        for name in [
+            "index.html",
+            "style.css",
+            "zip",
+            "notes.zipper",
+            "photo.gzip",
+        ] {
+            assert!(!has_archive_extension(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn a_lone_html_file_becomes_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let upload = dir.path().join("upload");
+        std::fs::write(&upload, b"<h1>hi</h1>").unwrap();
+        assert!(ArchiveFormat::detect(&upload).is_err());
+        assert!(!has_archive_extension("about.html"));
+
+        let dest = dir.path().join("site");
+        std::fs::create_dir(&dest).unwrap();
+        let mut limits = limits_for(MB, 11);
+        stage_files(&[("about.html".to_string(), &upload)], &dest, &mut limits).unwrap();
+        assert_eq!(
+            std::fs::read(dest.join("index.html")).unwrap(),
+            b"<h1>hi</h1>"
+        );
+        assert!(!dest.join("about.html").exists());
+    }
+
+    #[test]
+    fn sanitize_archive_path_rejects_parent_dirs() {
+        assert!(sanitize_archive_path("../escape.html").is_err());
+        assert!(sanitize_archive_path("docs/../../escape.html").is_err());
+        assert!(sanitize_archive_path("docs/..").is_err());
+    }
+
+    #[test]
+    fn sanitize_archive_path_rejects_absolute_paths() {
+        assert!(sanitize_archive_path("/etc/passwd").is_err());
+        assert!(sanitize_archive_path("//server/share").is_err());
+    }
+
+    #[test]
+    fn sanitize_archive_path_rejects_dotfiles() {
+        assert!(sanitize_archive_path(".env").is_err());
+        assert!(sanitize_archive_path(".git/config").is_err());
+        assert!(sanitize_archive_path("site/.htaccess").is_err());
+    }
+
+    #[test]
+    fn sanitize_archive_path_normalizes_harmless_paths() {
+        assert_eq!(
+            sanitize_archive_path("./docs//index.html").unwrap(),
+            PathBuf::from("docs/index.html")
+        );
+        assert_eq!(
+            sanitize_archive_path("docs/").unwrap(),
+            PathBuf::from("docs")
+        );
+        assert_eq!(sanitize_archive_path("").unwrap(), PathBuf::from("."));
+        assert_eq!(sanitize_archive_path("./").unwrap(), PathBuf::from("."));
+    }
+}
@@ -11,6 +11,7 @@ use axum::{
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 
+use crate::config::Config;
 use crate::error::AppError;
 use crate::AppState;
 
@@ -93,16 +94,10 @@ where
             .and_then(|v| v.to_str().ok())
             .unwrap_or("");
 
+        let name = session_cookie_name(&app_state.config);
         let token = cookie_header
             .split(';')
-            .find_map(|cookie| {
-                let cookie = cookie.trim();
-                if cookie.starts_with("token=") {
-                    Some(cookie.trim_start_matches("token="))
-                } else {
-                    None
-                }
-            });
+            .find_map(|cookie| cookie.trim().strip_prefix(name)?.strip_prefix('='));
 
         let token = match token {
             Some(t) => t,
@@ -160,13 +155,8 @@ pub struct LoginForm {
     pub password: String,
 }
 
-pub async fn api_register(
-    State(state): State<AppState>,
-    Form(form): Form<RegisterForm>,
-) -> Result<Response, AppError> {
-    let username = form.username.trim().to_lowercase();
-
-    // Validate username
+/// Rules for a new username. Usernames become URL paths and, in subdomain mode, host names.
+pub fn validate_username(username: &str) -> Result<(), AppError> {
     if username.is_empty() || username.len() > 32 {
         return Err(AppError::BadRequest("Username must be 1-32 characters".to_string()));
     }
@@ -178,9 +168,37 @@ pub async fn api_register(
 
     // Reserved names
     let reserved = ["admin", "api", "static", "dashboard", "register", "login", "logout"];
-    if reserved.contains(&username.as_str()) {
+    if reserved.contains(&username) {
         return Err(AppError::BadRequest("This username is reserved".to_string()));
     }
+    Ok(())
+}
+
+/// Name of the session cookie. Over HTTPS the `__Host-` prefix makes browsers refuse it
+/// unless it is `Secure`, host-only and for `/`, so a sibling subdomain can't plant its own
+/// session. Browsers don't keep `Secure` cookies over plain HTTP, so there it stays `token`.
+pub fn session_cookie_name(config: &Config) -> &'static str {
+    if config.https_management() {
+        "__Host-token"
+    } else {
+        "token"
+    }
+}
+
+/// The session cookie deliberately has no `Domain` attribute: browsers then keep it to the
+/// exact management host and never send it to the content origin or other subdomains.
+fn session_cookie(config: &Config, token: &str, max_age: u32) -> String {
+    let name = session_cookie_name(config);
+    let secure = if config.https_management() { " Secure;" } else { "" };
+    format!("{name}={token}; Path=/;{secure} HttpOnly; SameSite=Lax; Max-Age={max_age}")
+}
+
+pub async fn api_register(
+    State(state): State<AppState>,
+    Form(form): Form<RegisterForm>,
+) -> Result<Response, AppError> {
+    let username = form.username.trim().to_lowercase();
+    validate_username(&username)?;
 
     // Hash password and register (validates invite code + creates user atomically)
     let password_hash = hash_password(&form.password)?;
@@ -192,7 +210,7 @@ pub async fn api_register(
 
     // Create token and set cookie
     let token = create_token(&username, false, &state.config.jwt_secret)?;
-    let cookie = format!("token={token}; Path=/; HttpOnly; SameSite=Lax; Max-Age=86400");
+    let cookie = session_cookie(&state.config, &token, 86400);
 
     Ok((
         StatusCode::SEE_OTHER,
@@ -216,7 +234,7 @@ pub async fn api_login(
             return Err(AppError::Unauthorized("Invalid credentials".to_string()));
         }
         let token = create_token("admin", true, &state.config.jwt_secret)?;
-        let cookie = format!("token={token}; Path=/; HttpOnly; SameSite=Lax; Max-Age=86400");
+        let cookie = session_cookie(&state.config, &token, 86400);
         return Ok((
             StatusCode::SEE_OTHER,
             [
@@ -237,7 +255,7 @@ pub async fn api_login(
     }
 
     let token = create_token(&username, user.is_admin, &state.config.jwt_secret)?;
-    let cookie = format!("token={token}; Path=/; HttpOnly; SameSite=Lax; Max-Age=86400");
+    let cookie = session_cookie(&state.config, &token, 86400);
 
     Ok((
         StatusCode::SEE_OTHER,
@@ -249,12 +267,12 @@ pub async fn api_login(
         .into_response())
 }
 
-pub async fn api_logout() -> Response {
-    let cookie = "token=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0";
+pub async fn api_logout(State(state): State<AppState>) -> Response {
+    let cookie = session_cookie(&state.config, "", 0);
     (
         StatusCode::SEE_OTHER,
         [
-            (header::SET_COOKIE, cookie.to_string()),
+            (header::SET_COOKIE, cookie),
             (header::LOCATION, "/".to_string()),
         ],
     )
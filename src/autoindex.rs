@@ -0,0 +1,156 @@
+//! HTML directory listings for folders without an `index.html`, when the site enables them
+//! or the folder contains an `_autoindex` marker file.
+
+use axum::{
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use std::path::Path;
+
+use crate::error::AppError;
+
+/// An empty file with this name turns on listings for the folder it sits in.
+pub const MARKER_FILE: &str = "_autoindex";
+
+#[derive(askama::Template)]
+#[template(path = "autoindex.html")]
+struct AutoindexTemplate {
+    path: String,
+    has_parent: bool,
+    entries: Vec<ListingEntry>,
+}
+
+struct ListingEntry {
+    name: String,
+    href: String,
+    is_dir: bool,
+    size: String,
+    modified: String,
+}
+
+pub fn has_marker(dir: &Path) -> bool {
+    dir.join(MARKER_FILE).is_file()
+}
+
+/// Render the listing for `dir`, the folder at `path` within a site served under `base`.
+/// `hidden` decides which entries are server-managed and left out.
+pub fn render(
+    dir: &Path,
+    base: &str,
+    path: &str,
+    hidden: impl Fn(&Path) -> bool,
+) -> Result<Response, AppError> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || hidden(&entry.path()) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let is_dir = metadata.is_dir();
+        let mut href = encode_segment(&name);
+        if is_dir {
+            href.push('/');
+        }
+        entries.push(ListingEntry {
+            name,
+            href,
+            is_dir,
+            size: if is_dir {
+                "-".to_string()
+            } else {
+                format_size(metadata.len())
+            },
+            modified: metadata
+                .modified()
+                .map(httpdate::fmt_http_date)
+                .unwrap_or_default(),
+        });
+    }
+
+    // Folders first, then case-insensitive by name
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+
+    let template = AutoindexTemplate {
+        // The site's root has no parent worth linking to, whatever its base
+        has_parent: !path.trim_matches('/').is_empty(),
+        path: format!("{base}{path}"),
+        entries,
+    };
+    Ok((
+        StatusCode::OK,
+        [(header::CACHE_CONTROL, "no-cache")],
+        Html(template.to_string()),
+    )
+        .into_response())
+}
+
+/// Percent-encode a file name for use as a relative link.
+fn encode_segment(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+fn format_size(size: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    match size {
+        s if s < KB => format!("{s} B"),
+        s if s < MB => format!("{:.1} KB", s as f64 / KB as f64),
+        s => format!("{:.1} MB", s as f64 / MB as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn listing(base: &str, path: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("page.html"), "hi").unwrap();
+        let response = render(dir.path(), base, path, |_| false).unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn subfolders_link_to_their_parent() {
+        for base in ["/", "/alice/"] {
+            let body = listing(base, "docs/").await;
+            assert!(body.contains(r#"href="../""#), "{base}");
+            assert!(body.contains(&format!("Index of {base}docs/")), "{base}");
+            assert!(body.contains(r#"href="page.html""#), "{base}");
+        }
+    }
+
+    #[tokio::test]
+    async fn the_site_root_has_no_parent_link() {
+        for base in ["/", "/alice/"] {
+            assert!(!listing(base, "").await.contains(r#"href="../""#), "{base}");
+        }
+    }
+
+    #[test]
+    fn file_names_are_percent_encoded() {
+        assert_eq!(encode_segment("a b#?.html"), "a%20b%23%3F.html");
+        assert_eq!(
+            encode_segment("หน้า"),
+            "%E0%B8%AB%E0%B8%99%E0%B9%89%E0%B8%B2"
+        );
+    }
+}
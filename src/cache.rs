@@ -0,0 +1,183 @@
+//! Conditional request handling for hosted site files: validators derived from file
+//! metadata, `If-None-Match` / `If-Modified-Since` evaluation and `Cache-Control` policy.
+
+use axum::http::{header, HeaderMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+
+/// The validators sent with every hosted file.
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    /// A strong ETag from size and modification time. Files in a release never change once
+    /// it is published (file manager edits become a new release), so size and mtime
+    /// identify the content.
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        let last_modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let nanos = last_modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Self {
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), nanos),
+            last_modified,
+        }
+    }
+
+    /// Each content encoding is a different representation and needs its own tag.
+    pub fn for_encoding(self, encoding: &str) -> Self {
+        Self {
+            etag: format!("{}-{encoding}\"", self.etag.trim_end_matches('"')),
+            ..self
+        }
+    }
+
+    /// A file served with its URLs rewritten is another representation too.
+    pub fn for_rewrite(self) -> Self {
+        Self {
+            etag: format!("{}-rewritten\"", self.etag.trim_end_matches('"')),
+            ..self
+        }
+    }
+
+    /// Compression on the fly changes the bytes but not the meaning, which is all a weak tag
+    /// promises. Ranges need a strong one, so `If-Range` never matches a weak tag.
+    pub fn weak(self) -> Self {
+        Self {
+            etag: format!("W/{}", self.etag),
+            ..self
+        }
+    }
+
+    pub fn last_modified_header(&self) -> String {
+        httpdate::fmt_http_date(self.last_modified)
+    }
+
+    /// Whether the client's cached copy is still current (RFC 9110 §13.2.2). `If-None-Match`
+    /// takes precedence; `If-Modified-Since` is only consulted without it, and only an exact
+    /// match counts: a rollback brings back files older than the client's copy.
+    pub fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+            let Ok(value) = value.to_str() else {
+                return false;
+            };
+            return value.split(',').map(str::trim).any(|tag| {
+                // If-None-Match uses weak comparison
+                tag == "*" || opaque_tag(tag) == opaque_tag(&self.etag)
+            });
+        }
+
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+            .is_some_and(|since| truncate_to_seconds(self.last_modified) == since)
+    }
+
+    /// Whether a `Range` request may be honoured. With `If-Range`, the client only wants
+    /// the partial content if its copy is identical; otherwise it gets the whole file.
+    pub fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers.get(header::IF_RANGE) else {
+            return true;
+        };
+        let Ok(value) = value.to_str() else {
+            return false;
+        };
+        if value.starts_with('"') || value.starts_with("W/") {
+            // Strong comparison: a weak tag never matches
+            return value == self.etag && !self.etag.starts_with("W/");
+        }
+        httpdate::parse_http_date(value)
+            .is_ok_and(|date| date == truncate_to_seconds(self.last_modified))
+    }
+}
+
+fn opaque_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// HTTP dates have one-second resolution.
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    UNIX_EPOCH + std::time::Duration::from_secs(secs)
+}
+
+/// Pages are revalidated so a new deploy shows up immediately; other assets use the
+/// longer-lived policy.
+pub fn cache_control<'a>(config: &'a Config, ext: &str) -> &'a str {
+    match ext.to_lowercase().as_str() {
+        "html" | "htm" => &config.cache_control_html,
+        _ => &config.cache_control_assets,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::time::Duration;
+
+    fn validators(secs: u64) -> Validators {
+        Validators {
+            etag: "\"10-abc\"".to_string(),
+            last_modified: UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    fn if_modified_since(secs: u64) -> HeaderMap {
+        let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&date).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn if_modified_since_matches_the_same_second() {
+        let mut v = validators(1_000_000);
+        v.last_modified += Duration::from_millis(400);
+        assert!(v.not_modified(&if_modified_since(1_000_000)));
+    }
+
+    #[test]
+    fn if_modified_since_newer_than_the_file_is_not_a_match() {
+        // The client cached a newer release before a rollback
+        assert!(!validators(1_000_000).not_modified(&if_modified_since(2_000_000)));
+        assert!(!validators(2_000_000).not_modified(&if_modified_since(1_000_000)));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence() {
+        let mut headers = if_modified_since(1_000_000);
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!validators(1_000_000).not_modified(&headers));
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"other\", W/\"10-abc\""),
+        );
+        assert!(validators(2_000_000).not_modified(&headers));
+    }
+
+    #[test]
+    fn weak_tags_match_if_none_match_but_never_if_range() {
+        let weak = validators(1_000_000).weak();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"10-abc\""));
+        assert!(weak.not_modified(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("W/\"10-abc\""));
+        assert!(!weak.if_range_matches(&headers));
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("\"10-abc\""));
+        assert!(validators(1_000_000).if_range_matches(&headers));
+    }
+}
@@ -0,0 +1,212 @@
+//! Precompressed `.br` / `.gz` sidecars for text assets and `Accept-Encoding` negotiation.
+//!
+//! Sidecars live next to a release rather than inside it (`releases/{user}/{id}.compressed/`),
+//! so they never show up in the file manager or count against the quota.
+
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Extensions worth compressing. Images, video and fonts are already compressed.
+const COMPRESSIBLE: &[&str] = &["html", "htm", "css", "js", "mjs", "svg", "json"];
+
+/// Below this, the encoding headers cost more than compression saves.
+pub const MIN_SIZE: u64 = 256;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gz",
+        }
+    }
+}
+
+pub fn is_compressible(ext: &str) -> bool {
+    COMPRESSIBLE.contains(&ext.to_lowercase().as_str())
+}
+
+/// Where the sidecars for a release are kept.
+pub fn sidecar_dir(release_dir: &Path) -> PathBuf {
+    release_dir.with_extension("compressed")
+}
+
+/// Write `.br` and `.gz` sidecars for every compressible file in a release on a blocking
+/// thread, after it has gone live: brotli at maximum quality is slow, and until a sidecar
+/// is in place its file is compressed per request. Failures only cost the optimisation, so
+/// they are logged rather than failing the deploy.
+pub fn precompress_release(release_dir: PathBuf) {
+    tokio::task::spawn_blocking(move || precompress_now(&release_dir));
+}
+
+fn precompress_now(release_dir: &Path) {
+    let sidecars = sidecar_dir(release_dir);
+    if let Err(e) = precompress_dir(release_dir, release_dir, &sidecars) {
+        tracing::warn!(
+            "Failed to precompress {}: {e}",
+            release_dir.display()
+        );
+    }
+}
+
+fn precompress_dir(base: &Path, current: &Path, sidecars: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(current)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            precompress_dir(base, &path, sidecars)?;
+            continue;
+        }
+
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        if !is_compressible(ext) || entry.metadata()?.len() < MIN_SIZE {
+            continue;
+        }
+
+        let data = std::fs::read(&path)?;
+        let relative = path.strip_prefix(base).unwrap_or(&path);
+        for encoding in [Encoding::Brotli, Encoding::Gzip] {
+            let compressed = compress(&data, encoding)?;
+            // Not worth serving if it didn't shrink
+            if compressed.len() >= data.len() {
+                continue;
+            }
+            // Renamed into place, since the release is already being served
+            let target = sidecar_path(sidecars, relative, encoding);
+            let Some(parent) = target.parent() else {
+                continue;
+            };
+            std::fs::create_dir_all(parent)?;
+            let mut temp = tempfile::NamedTempFile::new_in(parent)?;
+            temp.write_all(&compressed)?;
+            temp.persist(target).map_err(|e| e.error)?;
+        }
+    }
+    Ok(())
+}
+
+fn compress(data: &[u8], encoding: Encoding) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match encoding {
+        Encoding::Brotli => {
+            // Maximum quality: this runs once per deploy, off the request path
+            let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+            writer.write_all(data)?;
+        }
+        Encoding::Gzip => {
+            let mut writer =
+                flate2::write::GzEncoder::new(&mut out, flate2::Compression::best());
+            writer.write_all(data)?;
+            writer.finish()?;
+        }
+    }
+    Ok(out)
+}
+
+fn sidecar_path(sidecars: &Path, relative: &Path, encoding: Encoding) -> PathBuf {
+    let mut name = relative.as_os_str().to_owned();
+    name.push(".");
+    name.push(encoding.extension());
+    sidecars.join(name)
+}
+
+/// Find the best sidecar the client accepts for `file` inside the release at `site_root`.
+/// A sidecar older than its file (edited after deploy) is stale and skipped.
+pub fn find_sidecar(
+    site_root: &Path,
+    file: &Path,
+    modified: std::time::SystemTime,
+    headers: &HeaderMap,
+) -> Option<(Encoding, PathBuf)> {
+    let relative = file.strip_prefix(site_root).ok()?;
+    let sidecars = sidecar_dir(site_root);
+
+    accepted_encodings(headers).into_iter().find_map(|encoding| {
+        let path = sidecar_path(&sidecars, relative, encoding);
+        let sidecar_modified = std::fs::metadata(&path).ok()?.modified().ok()?;
+        (sidecar_modified >= modified).then_some((encoding, path))
+    })
+}
+
+/// Encodings from `Accept-Encoding` that we have sidecars for, best first.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<Encoding> {
+    let Some(value) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Vec::new();
+    };
+
+    let mut accepted: Vec<(Encoding, f32)> = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let encoding = match parts.next()?.trim().to_ascii_lowercase().as_str() {
+                "br" => Encoding::Brotli,
+                "gzip" => Encoding::Gzip,
+                _ => return None,
+            };
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (quality > 0.0).then_some((encoding, quality))
+        })
+        .collect();
+
+    // Stable sort keeps brotli ahead of gzip on equal quality
+    accepted.sort_by_key(|(encoding, _)| *encoding != Encoding::Brotli);
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    accepted.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+/// Whether the client accepts an encoding we compress with.
+pub fn accepts_compression(headers: &HeaderMap) -> bool {
+    !accepted_encodings(headers).is_empty()
+}
+
+/// Content types compressed on the fly when they have no sidecar.
+pub fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("image/svg+xml")
+}
+
+/// Predicate for on-the-fly compression of files without a sidecar: whole text responses
+/// only. Partial content must stay byte-for-byte what the client asked for, and so must
+/// anything carrying a strong ETag; hosted files that will be compressed here are given a
+/// weak one.
+pub fn compress_on_the_fly(
+    status: StatusCode,
+    _: Version,
+    headers: &HeaderMap,
+    _: &Extensions,
+) -> bool {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let large_enough = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .is_some_and(|len| len >= MIN_SIZE);
+    let strong_tag = headers
+        .get(header::ETAG)
+        .is_some_and(|tag| !tag.as_bytes().starts_with(b"W/"));
+
+    status == StatusCode::OK && is_text(content_type) && large_enough && !strong_tag
+}
@@ -6,9 +6,35 @@ pub struct Config {
     pub data_dir: PathBuf,
     pub disk_quota_bytes: u64,
     pub max_upload_bytes: u64,
+    pub deploy_retention: usize,
+    pub max_archive_entries: u64,
+    pub max_path_depth: usize,
+    pub max_path_length: usize,
+    pub max_compression_ratio: u64,
+    pub cache_control_html: String,
+    pub cache_control_assets: String,
+    pub precompress: bool,
     pub admin_password: String,
     pub jwt_secret: String,
     pub listen_addr: String,
+    /// Address for built-in HTTPS. When set, `listen_addr` only redirects to HTTPS.
+    pub tls_listen_addr: Option<String>,
+    /// Host names of the server itself that need certificates. Verified custom domains
+    /// are added automatically.
+    pub tls_domains: Vec<String>,
+    pub acme_directory: String,
+    pub acme_contact: Option<String>,
+    /// Extra root certificate for talking to the ACME server, e.g. Pebble's test CA.
+    pub acme_ca_file: Option<PathBuf>,
+    /// Base origin for subdomain-per-user mode: with `https://pages.example`, alice's site
+    /// is served at `https://alice.pages.example/`.
+    pub subdomain_origin: Option<String>,
+    /// Origin user sites are served from, e.g. `https://pages-usercontent.example`. It should
+    /// be on a different registrable domain from the management UI, so it is another site.
+    pub content_origin: Option<String>,
+    /// Origin of the dashboard and API, e.g. `https://pages.example`. Custom domains can never
+    /// claim it.
+    pub management_origin: Option<String>,
 }
 
 impl Config {
@@ -23,6 +49,42 @@ impl Config {
             .parse()
             .expect("MAX_UPLOAD_MB must be a number");
 
+        let deploy_retention: usize = env::var("DEPLOY_RETENTION")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .expect("DEPLOY_RETENTION must be a number");
+
+        let max_archive_entries: u64 = env::var("MAX_ARCHIVE_ENTRIES")
+            .unwrap_or_else(|_| "10000".to_string())
+            .parse()
+            .expect("MAX_ARCHIVE_ENTRIES must be a number");
+
+        let max_path_depth: usize = env::var("MAX_PATH_DEPTH")
+            .unwrap_or_else(|_| "32".to_string())
+            .parse()
+            .expect("MAX_PATH_DEPTH must be a number");
+
+        let max_path_length: usize = env::var("MAX_PATH_LENGTH")
+            .unwrap_or_else(|_| "255".to_string())
+            .parse()
+            .expect("MAX_PATH_LENGTH must be a number");
+
+        let max_compression_ratio: u64 = env::var("MAX_COMPRESSION_RATIO")
+            .unwrap_or_else(|_| "100".to_string())
+            .parse()
+            .expect("MAX_COMPRESSION_RATIO must be a number");
+
+        let cache_control_html =
+            env::var("CACHE_CONTROL_HTML").unwrap_or_else(|_| "no-cache".to_string());
+
+        let cache_control_assets = env::var("CACHE_CONTROL_ASSETS")
+            .unwrap_or_else(|_| "public, max-age=3600".to_string());
+
+        let precompress: bool = env::var("PRECOMPRESS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("PRECOMPRESS must be true or false");
+
         let admin_password =
             env::var("ADMIN_PASSWORD").unwrap_or_else(|_| "admin".to_string());
 
@@ -34,13 +96,143 @@ impl Config {
         let listen_addr =
             env::var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
 
+        let tls_listen_addr = env::var("TLS_LISTEN_ADDR").ok().filter(|v| !v.is_empty());
+
+        let tls_domains = env::var("TLS_DOMAINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|d| d.trim().to_ascii_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+
+        let acme_directory = env::var("ACME_DIRECTORY")
+            .unwrap_or_else(|_| "https://acme-v02.api.letsencrypt.org/directory".to_string());
+
+        let acme_contact = env::var("ACME_CONTACT").ok().filter(|v| !v.is_empty());
+
+        let acme_ca_file = env::var("ACME_CA_FILE")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let subdomain_origin = origin_from_env("SUBDOMAIN_ORIGIN");
+
+        let content_origin = origin_from_env("CONTENT_ORIGIN");
+
+        let management_origin = origin_from_env("MANAGEMENT_ORIGIN");
+
         Self {
             data_dir,
             disk_quota_bytes: disk_quota_mb * 1024 * 1024,
             max_upload_bytes: max_upload_mb * 1024 * 1024,
+            deploy_retention: deploy_retention.max(1),
+            max_archive_entries,
+            max_path_depth,
+            max_path_length,
+            max_compression_ratio,
+            cache_control_html,
+            cache_control_assets,
+            precompress,
             admin_password,
             jwt_secret,
             listen_addr,
+            tls_listen_addr,
+            tls_domains,
+            acme_directory,
+            acme_contact,
+            acme_ca_file,
+            subdomain_origin,
+            content_origin,
+            management_origin,
+        }
+    }
+
+    /// Whether the dashboard is reached over HTTPS: `MANAGEMENT_ORIGIN` says so, or the
+    /// built-in TLS listener is on, which moves every plain HTTP request to HTTPS.
+    pub fn https_management(&self) -> bool {
+        self.tls_listen_addr.is_some()
+            || self
+                .management_origin
+                .as_deref()
+                .is_some_and(|origin| origin.starts_with("https://"))
+    }
+
+    /// Host (with port, if any) of the content origin.
+    pub fn content_host(&self) -> Option<&str> {
+        self.content_origin.as_deref().map(origin_host)
+    }
+
+    /// Host (with port, if any) that user subdomains sit under.
+    pub fn subdomain_host(&self) -> Option<&str> {
+        self.subdomain_origin.as_deref().map(origin_host)
+    }
+
+    /// Whether `domain` (lowercase, without a port) is one of the server's own host names or
+    /// sits under one. Those never route to a user's site as a custom domain.
+    pub fn is_platform_host(&self, domain: &str) -> bool {
+        let origins = [
+            &self.management_origin,
+            &self.content_origin,
+            &self.subdomain_origin,
+        ];
+        let hosts = origins
+            .into_iter()
+            .flatten()
+            .map(|origin| origin_host(origin))
+            .chain(self.tls_domains.iter().map(String::as_str));
+        hosts.map(strip_port).any(|host| {
+            let host = host.to_ascii_lowercase();
+            domain == host || domain.ends_with(&format!(".{host}"))
+        })
+    }
+
+    /// Hosts for user content that look like the same site as the management host, so
+    /// browsers would send them `SameSite` cookies. Only checked when `MANAGEMENT_ORIGIN` is
+    /// set, and only by host name: a shared parent domain is taken as a shared site.
+    pub fn same_site_content_hosts(&self) -> Vec<(&'static str, &str)> {
+        let Some(management) = self.management_origin.as_deref().map(origin_host) else {
+            return Vec::new();
+        };
+        let management = strip_port(management).to_ascii_lowercase();
+        // A parent of one label is a TLD, which doesn't make two hosts the same site
+        let parent = |host: &str| {
+            host.split_once('.')
+                .map(|(_, rest)| rest.to_string())
+                .filter(|rest| rest.contains('.'))
+        };
+        let related = |host: &str| {
+            let host = strip_port(host).to_ascii_lowercase();
+            host == management
+                || host.ends_with(&format!(".{management}"))
+                || management.ends_with(&format!(".{host}"))
+                || (parent(&host).is_some() && parent(&host) == parent(&management))
+        };
+        [
+            ("SUBDOMAIN_ORIGIN", self.subdomain_host()),
+            ("CONTENT_ORIGIN", self.content_host()),
+        ]
+        .into_iter()
+        .filter_map(|(name, host)| Some((name, host?)))
+        .filter(|(_, host)| related(host))
+        .collect()
+    }
+
+    /// Origin of a user's own subdomain, when subdomain mode is on. Usernames with `_`
+    /// aren't valid host names, so those sites stay on the path routes.
+    pub fn user_subdomain(&self, username: &str) -> Option<String> {
+        let origin = self.subdomain_origin.as_deref()?;
+        let (scheme, host) = origin.split_once("://")?;
+        (!username.contains('_')).then(|| format!("{scheme}://{username}.{host}"))
+    }
+
+    /// Public URL of a user's site.
+    pub fn site_url(&self, username: &str) -> String {
+        if let Some(origin) = self.user_subdomain(username) {
+            return format!("{origin}/");
+        }
+        match &self.content_origin {
+            Some(origin) => format!("{origin}/{username}/"),
+            None => format!("/{username}/"),
         }
     }
 
@@ -51,4 +243,43 @@ impl Config {
     pub fn sites_dir(&self) -> PathBuf {
         self.data_dir.join("sites")
     }
+
+    pub fn releases_dir(&self) -> PathBuf {
+        self.data_dir.join("releases")
+    }
+
+    pub fn uploads_dir(&self) -> PathBuf {
+        self.data_dir.join("uploads")
+    }
+
+    /// ACME account keys and issued certificates.
+    pub fn certs_dir(&self) -> PathBuf {
+        self.data_dir.join("certs")
+    }
+
+    pub fn tmp_dir(&self) -> PathBuf {
+        self.data_dir.join("tmp")
+    }
+}
+
+/// An optional `http(s)://host[:port]` origin, without a trailing slash.
+fn origin_from_env(name: &str) -> Option<String> {
+    let value = env::var(name).ok().filter(|v| !v.is_empty())?;
+    assert!(
+        value.starts_with("http://") || value.starts_with("https://"),
+        "{name} must start with http:// or https://"
+    );
+    Some(value.trim_end_matches('/').to_string())
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+fn origin_host(origin: &str) -> &str {
+    let rest = origin.split_once("://").map_or(origin, |(_, rest)| rest);
+    rest.split('/').next().unwrap_or(rest)
 }
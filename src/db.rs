@@ -26,6 +26,157 @@ pub struct InviteCode {
     pub used_at: Option<String>,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Deployment {
+    pub id: i64,
+    pub username: String,
+    pub number: i64,
+    pub created_at: String,
+    pub size_bytes: u64,
+    pub file_count: u64,
+    pub uploaded_by: String,
+    pub is_active: bool,
+    pub kind: DeploymentKind,
+}
+
+/// How a release came about, which decides how long it is retained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentKind {
+    /// An uploaded archive or file.
+    Upload,
+    /// A file manager change to the release before it.
+    Edit,
+}
+
+impl DeploymentKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeploymentKind::Upload => "upload",
+            DeploymentKind::Edit => "edit",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "edit" => DeploymentKind::Edit,
+            _ => DeploymentKind::Upload,
+        }
+    }
+}
+
+impl Deployment {
+    fn from_row(row: &rusqlite::Row<'_>) -> Result<Self, rusqlite::Error> {
+        Ok(Deployment {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            number: row.get(2)?,
+            created_at: row.get(3)?,
+            size_bytes: row.get::<_, i64>(4)? as u64,
+            file_count: row.get::<_, i64>(5)? as u64,
+            uploaded_by: row.get(6)?,
+            is_active: row.get::<_, i32>(7)? != 0,
+            kind: DeploymentKind::from_db(&row.get::<_, String>(8)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingUpload {
+    pub id: String,
+    pub username: String,
+    pub filename: String,
+    pub length: u64,
+    pub offset: u64,
+}
+
+impl PendingUpload {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            filename: row.get(2)?,
+            length: row.get::<_, i64>(3)? as u64,
+            offset: row.get::<_, i64>(4)? as u64,
+        })
+    }
+}
+
+/// Who may see a site.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Open to anyone with the link, but asks search engines not to index it.
+    Unlisted,
+    /// Any signed-in user.
+    Users,
+    /// Only the owner and admins.
+    Private,
+}
+
+impl Visibility {
+    fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Users => "users",
+            Visibility::Private => "private",
+        }
+    }
+
+    // An unknown value keeps the site closed rather than exposing it
+    fn from_db(value: &str) -> Self {
+        match value {
+            "public" => Visibility::Public,
+            "unlisted" => Visibility::Unlisted,
+            "users" => Visibility::Users,
+            _ => Visibility::Private,
+        }
+    }
+}
+
+/// Per-site serving options, toggled by the owner from the dashboard.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SiteSettings {
+    pub spa_fallback: bool,
+    pub clean_urls: bool,
+    pub autoindex: bool,
+    /// Prefix root-relative URLs in pages and stylesheets with `/{username}/`.
+    pub rewrite_urls: bool,
+    pub visibility: Visibility,
+    /// Set through its own endpoint; the hash itself never leaves the database.
+    pub password_protected: bool,
+}
+
+/// A domain a user wants their site served at. It only routes to the site once verified.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CustomDomain {
+    pub domain: String,
+    pub username: String,
+    pub token: String,
+    pub verified_at: Option<String>,
+    pub created_at: String,
+}
+
+impl CustomDomain {
+    fn from_row(row: &rusqlite::Row<'_>) -> Result<Self, rusqlite::Error> {
+        Ok(CustomDomain {
+            domain: row.get(0)?,
+            username: row.get(1)?,
+            token: row.get(2)?,
+            verified_at: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    }
+}
+
+const CUSTOM_DOMAIN_COLUMNS: &str = "domain, username, token, verified_at, created_at";
+
+const DEPLOYMENT_COLUMNS: &str =
+    "id, username, number, created_at, size_bytes, file_count, uploaded_by, is_active, kind";
+
 impl Db {
     pub fn open(path: &Path) -> Result<Self, AppError> {
         let conn = Connection::open(path)
@@ -57,7 +208,52 @@ impl Db {
                 created_at TEXT NOT NULL DEFAULT (datetime('now')),
                 used_by TEXT REFERENCES users(username),
                 used_at TEXT
-            );",
+            );
+
+            CREATE TABLE IF NOT EXISTS deployments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                number INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                size_bytes INTEGER NOT NULL,
+                file_count INTEGER NOT NULL,
+                uploaded_by TEXT NOT NULL,
+                is_active INTEGER NOT NULL DEFAULT 0,
+                kind TEXT NOT NULL DEFAULT 'upload'
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_deployments_username ON deployments(username, id);
+
+            CREATE TABLE IF NOT EXISTS uploads (
+                id TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                filename TEXT NOT NULL,
+                length INTEGER NOT NULL,
+                offset INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE IF NOT EXISTS site_settings (
+                username TEXT PRIMARY KEY,
+                spa_fallback INTEGER NOT NULL DEFAULT 0,
+                clean_urls INTEGER NOT NULL DEFAULT 0,
+                autoindex INTEGER NOT NULL DEFAULT 0,
+                password_hash TEXT,
+                visibility TEXT NOT NULL DEFAULT 'public',
+                share_key TEXT,
+                rewrite_urls INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE TABLE IF NOT EXISTS custom_domains (
+                domain TEXT PRIMARY KEY,
+                username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+                token TEXT NOT NULL,
+                verified_at TEXT,
+                verified_by TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_custom_domains_username ON custom_domains(username);",
         )?;
         Ok(())
     }
@@ -160,16 +356,407 @@ impl Db {
         )?;
         Ok(rows > 0)
     }
+
+    pub fn create_deployment(
+        &self,
+        username: &str,
+        size_bytes: u64,
+        file_count: u64,
+        uploaded_by: &str,
+        kind: DeploymentKind,
+    ) -> Result<Deployment, AppError> {
+        let conn = self.conn.lock().unwrap();
+
+        // Release numbers count up per user, independent of the global row id
+        let number: i64 = conn.query_row(
+            "SELECT COALESCE(MAX(number), 0) + 1 FROM deployments WHERE username = ?1",
+            params![username],
+            |row| row.get(0),
+        )?;
+
+        conn.execute(
+            "INSERT INTO deployments (username, number, size_bytes, file_count, uploaded_by, kind)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                username,
+                number,
+                size_bytes as i64,
+                file_count as i64,
+                uploaded_by,
+                kind.as_str()
+            ],
+        )?;
+
+        let id = conn.last_insert_rowid();
+        let deployment = conn.query_row(
+            &format!("SELECT {DEPLOYMENT_COLUMNS} FROM deployments WHERE id = ?1"),
+            params![id],
+            Deployment::from_row,
+        )?;
+        Ok(deployment)
+    }
+
+    pub fn get_deployment(&self, username: &str, id: i64) -> Result<Option<Deployment>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let deployment = conn
+            .query_row(
+                &format!(
+                    "SELECT {DEPLOYMENT_COLUMNS} FROM deployments WHERE id = ?1 AND username = ?2"
+                ),
+                params![id, username],
+                Deployment::from_row,
+            )
+            .optional()?;
+        Ok(deployment)
+    }
+
+    pub fn list_deployments(&self, username: &str) -> Result<Vec<Deployment>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DEPLOYMENT_COLUMNS} FROM deployments WHERE username = ?1 ORDER BY id DESC"
+        ))?;
+        let deployments = stmt
+            .query_map(params![username], Deployment::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(deployments)
+    }
+
+    /// Mark one deployment as live, or none at all when `id` is `None`.
+    pub fn set_active_deployment(&self, username: &str, id: Option<i64>) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE deployments SET is_active = (id IS ?2) WHERE username = ?1",
+            params![username, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_deployment(&self, id: i64) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM deployments WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Returns uploads beyond the newest `keep`. Edits only count as part of the upload they
+    /// were made to and expire with it. The active deployment is never returned.
+    pub fn expired_deployments(
+        &self,
+        username: &str,
+        keep: usize,
+    ) -> Result<Vec<Deployment>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DEPLOYMENT_COLUMNS} FROM deployments WHERE username = ?1 ORDER BY id DESC"
+        ))?;
+        let deployments = stmt
+            .query_map(params![username], Deployment::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Newest first, so the edits made to an upload come up just before it
+        let mut uploads = 0;
+        let mut edits = Vec::new();
+        let mut expired = Vec::new();
+        for deployment in deployments {
+            if deployment.kind == DeploymentKind::Edit {
+                edits.push(deployment);
+                continue;
+            }
+            uploads += 1;
+            let upload_edits = std::mem::take(&mut edits);
+            if uploads > keep {
+                expired.extend(upload_edits);
+                expired.push(deployment);
+            }
+        }
+        expired.retain(|d| !d.is_active);
+        Ok(expired)
+    }
+
+    pub fn create_upload(
+        &self,
+        id: &str,
+        username: &str,
+        filename: &str,
+        length: u64,
+    ) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO uploads (id, username, filename, length) VALUES (?1, ?2, ?3, ?4)",
+            params![id, username, filename, length as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_upload(&self, id: &str) -> Result<Option<PendingUpload>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let upload = conn
+            .query_row(
+                "SELECT id, username, filename, length, offset FROM uploads WHERE id = ?1",
+                params![id],
+                PendingUpload::from_row,
+            )
+            .optional()?;
+        Ok(upload)
+    }
+
+    /// A user's unfinished uploads, oldest first.
+    pub fn pending_uploads(&self, username: &str) -> Result<Vec<PendingUpload>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, username, filename, length, offset FROM uploads
+             WHERE username = ?1 ORDER BY created_at, rowid",
+        )?;
+        let uploads = stmt
+            .query_map(params![username], PendingUpload::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(uploads)
+    }
+
+    pub fn set_upload_offset(&self, id: &str, offset: u64) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE uploads SET offset = ?2 WHERE id = ?1",
+            params![id, offset as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_upload(&self, id: &str) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM uploads WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Remove uploads started more than `max_age_hours` ago and return their ids.
+    pub fn expire_uploads(&self, max_age_hours: u32) -> Result<Vec<String>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let cutoff = format!("-{max_age_hours} hours");
+        let mut stmt =
+            conn.prepare("SELECT id FROM uploads WHERE created_at < datetime('now', ?1)")?;
+        let ids = stmt
+            .query_map(params![cutoff], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        conn.execute(
+            "DELETE FROM uploads WHERE created_at < datetime('now', ?1)",
+            params![cutoff],
+        )?;
+        Ok(ids)
+    }
+
+    /// Settings for a site, or the defaults if the owner never changed them.
+    pub fn get_site_settings(&self, username: &str) -> Result<SiteSettings, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let settings = conn
+            .query_row(
+                "SELECT spa_fallback, clean_urls, autoindex, password_hash IS NOT NULL,
+                        visibility, rewrite_urls
+                 FROM site_settings WHERE username = ?1",
+                params![username],
+                |row| {
+                    Ok(SiteSettings {
+                        spa_fallback: row.get::<_, i32>(0)? != 0,
+                        clean_urls: row.get::<_, i32>(1)? != 0,
+                        autoindex: row.get::<_, i32>(2)? != 0,
+                        password_protected: row.get::<_, i32>(3)? != 0,
+                        visibility: Visibility::from_db(&row.get::<_, String>(4)?),
+                        rewrite_urls: row.get::<_, i32>(5)? != 0,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    pub fn save_site_settings(
+        &self,
+        username: &str,
+        settings: &SiteSettings,
+    ) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO site_settings
+                (username, spa_fallback, clean_urls, autoindex, visibility, rewrite_urls)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(username) DO UPDATE SET
+                spa_fallback = excluded.spa_fallback,
+                clean_urls = excluded.clean_urls,
+                autoindex = excluded.autoindex,
+                visibility = excluded.visibility,
+                rewrite_urls = excluded.rewrite_urls",
+            params![
+                username,
+                settings.spa_fallback as i32,
+                settings.clean_urls as i32,
+                settings.autoindex as i32,
+                settings.visibility.as_str(),
+                settings.rewrite_urls as i32
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The argon2 hash visitors' passwords are checked against, if the site has one.
+    pub fn get_site_password(&self, username: &str) -> Result<Option<String>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let hash = conn
+            .query_row(
+                "SELECT password_hash FROM site_settings WHERE username = ?1",
+                params![username],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(hash.flatten())
+    }
+
+    pub fn set_site_password(
+        &self,
+        username: &str,
+        password_hash: Option<&str>,
+    ) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO site_settings (username, password_hash) VALUES (?1, ?2)
+             ON CONFLICT(username) DO UPDATE SET password_hash = excluded.password_hash",
+            params![username, password_hash],
+        )?;
+        Ok(())
+    }
+
+    /// The secret share links for a site are signed with, if any were ever made.
+    pub fn get_share_key(&self, username: &str) -> Result<Option<String>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let key = conn
+            .query_row(
+                "SELECT share_key FROM site_settings WHERE username = ?1",
+                params![username],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(key.flatten())
+    }
+
+    /// Replacing the key invalidates every share link signed with the old one.
+    pub fn set_share_key(&self, username: &str, share_key: &str) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO site_settings (username, share_key) VALUES (?1, ?2)
+             ON CONFLICT(username) DO UPDATE SET share_key = excluded.share_key",
+            params![username, share_key],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_custom_domain(
+        &self,
+        username: &str,
+        domain: &str,
+        token: &str,
+    ) -> Result<CustomDomain, AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO custom_domains (domain, username, token) VALUES (?1, ?2, ?3)",
+            params![domain, username, token],
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::SqliteFailure(err, _)
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                AppError::Conflict(format!("{domain} has already been added"))
+            }
+            other => AppError::Internal(other.to_string()),
+        })?;
+        let domain = conn.query_row(
+            &format!("SELECT {CUSTOM_DOMAIN_COLUMNS} FROM custom_domains WHERE domain = ?1"),
+            params![domain],
+            CustomDomain::from_row,
+        )?;
+        Ok(domain)
+    }
+
+    pub fn get_custom_domain(&self, domain: &str) -> Result<Option<CustomDomain>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let domain = conn
+            .query_row(
+                &format!("SELECT {CUSTOM_DOMAIN_COLUMNS} FROM custom_domains WHERE domain = ?1"),
+                params![domain],
+                CustomDomain::from_row,
+            )
+            .optional()?;
+        Ok(domain)
+    }
+
+    /// Domains of one user, or of everyone when `username` is `None`.
+    pub fn list_custom_domains(
+        &self,
+        username: Option<&str>,
+    ) -> Result<Vec<CustomDomain>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {CUSTOM_DOMAIN_COLUMNS} FROM custom_domains
+             WHERE ?1 IS NULL OR username = ?1 ORDER BY username, domain"
+        ))?;
+        let domains = stmt
+            .query_map(params![username], CustomDomain::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(domains)
+    }
+
+    /// `verified_by` records how ownership was shown: `token` or `admin`.
+    pub fn mark_domain_verified(&self, domain: &str, verified_by: &str) -> Result<(), AppError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE custom_domains SET verified_at = datetime('now'), verified_by = ?2
+             WHERE domain = ?1 AND verified_at IS NULL",
+            params![domain, verified_by],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_custom_domain(&self, domain: &str) -> Result<bool, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let rows = conn.execute(
+            "DELETE FROM custom_domains WHERE domain = ?1",
+            params![domain],
+        )?;
+        Ok(rows > 0)
+    }
+
+    /// Every domain that passed verification, for ordering certificates.
+    pub fn verified_domains(&self) -> Result<Vec<String>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT domain FROM custom_domains
+             WHERE verified_at IS NOT NULL",
+        )?;
+        let domains = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(domains)
+    }
+
+    /// The owner of a verified domain, for routing requests by `Host`.
+    pub fn verified_domain_owner(&self, domain: &str) -> Result<Option<String>, AppError> {
+        let conn = self.conn.lock().unwrap();
+        let username = conn
+            .query_row(
+                "SELECT username FROM custom_domains
+                 WHERE domain = ?1 AND verified_at IS NOT NULL",
+                params![domain],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(username)
+    }
 }
 
-trait OptionalRow {
-    fn optional(self) -> Result<Option<User>, rusqlite::Error>;
+trait OptionalRow<T> {
+    fn optional(self) -> Result<Option<T>, rusqlite::Error>;
 }
 
-impl OptionalRow for Result<User, rusqlite::Error> {
-    fn optional(self) -> Result<Option<User>, rusqlite::Error> {
+impl<T> OptionalRow<T> for Result<T, rusqlite::Error> {
+    fn optional(self) -> Result<Option<T>, rusqlite::Error> {
         match self {
-            Ok(user) => Ok(Some(user)),
+            Ok(row) => Ok(Some(row)),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(e),
         }
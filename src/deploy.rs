@@ -0,0 +1,362 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use std::collections::HashSet;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::auth::AuthUser;
+use crate::compress;
+use crate::db::{Deployment, DeploymentKind};
+use crate::error::AppError;
+use crate::AppState;
+
+/// Per-user deploy lock so that publishing, rollback and deletion never interleave.
+#[derive(Clone, Default)]
+pub struct DeployLocks {
+    active: Arc<Mutex<HashSet<String>>>,
+}
+
+pub struct DeployGuard {
+    locks: DeployLocks,
+    key: String,
+}
+
+impl DeployLocks {
+    pub fn acquire(&self, username: &str) -> Result<DeployGuard, AppError> {
+        if !self.active.lock().unwrap().insert(username.to_string()) {
+            return Err(AppError::Conflict(
+                "Another deploy for this site is in progress, please try again".to_string(),
+            ));
+        }
+        Ok(DeployGuard {
+            locks: self.clone(),
+            key: username.to_string(),
+        })
+    }
+
+    /// Lock a single resumable upload so two requests can't append to it at once.
+    pub fn acquire_upload(&self, id: &str) -> Result<DeployGuard, AppError> {
+        // ':' never appears in usernames, so these keys can't collide with site locks
+        let key = format!("upload:{id}");
+        if !self.active.lock().unwrap().insert(key.clone()) {
+            return Err(AppError::Conflict(
+                "This upload is already receiving data".to_string(),
+            ));
+        }
+        Ok(DeployGuard {
+            locks: self.clone(),
+            key,
+        })
+    }
+}
+
+impl Drop for DeployGuard {
+    fn drop(&mut self) {
+        self.locks.active.lock().unwrap().remove(&self.key);
+    }
+}
+
+pub async fn list_deployments(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let deployments = state.db.list_deployments(&auth.username)?;
+    Ok(Json(serde_json::json!({
+        "deployments": deployments,
+        "retention": state.config.deploy_retention,
+    })))
+}
+
+pub async fn rollback_deployment(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let _guard = state.deploy_locks.acquire(&auth.username)?;
+
+    let deployment = state
+        .db
+        .get_deployment(&auth.username, id)?
+        .ok_or_else(|| AppError::NotFound("Deployment not found".to_string()))?;
+
+    let release_dir = release_dir(&state, &auth.username, deployment.id);
+    if !release_dir.is_dir() {
+        return Err(AppError::NotFound(
+            "Files for this deployment are no longer available".to_string(),
+        ));
+    }
+
+    activate_release(&state, &auth.username, &release_dir)?;
+    state
+        .db
+        .set_active_deployment(&auth.username, Some(deployment.id))?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "deployment": Deployment { is_active: true, ..deployment },
+    })))
+}
+
+/// Record a new deployment, move the staged files into its release directory and make it live.
+pub fn publish_release(
+    state: &AppState,
+    username: &str,
+    uploaded_by: &str,
+    staged: &StdPath,
+    size_bytes: u64,
+    file_count: u64,
+) -> Result<Deployment, AppError> {
+    let guard = state.deploy_locks.acquire(username)?;
+    publish_release_locked(
+        &guard,
+        state,
+        username,
+        uploaded_by,
+        staged,
+        size_bytes,
+        file_count,
+        DeploymentKind::Upload,
+    )
+}
+
+/// [`publish_release`] for callers that already hold the user's deploy lock. An edit made on
+/// top of another edit replaces it, so a run of file manager changes leaves one release.
+#[allow(clippy::too_many_arguments)]
+pub fn publish_release_locked(
+    _guard: &DeployGuard,
+    state: &AppState,
+    username: &str,
+    uploaded_by: &str,
+    staged: &StdPath,
+    size_bytes: u64,
+    file_count: u64,
+    kind: DeploymentKind,
+) -> Result<Deployment, AppError> {
+    // Only the newest release is squashed; an edit to one that was rolled back to keeps it
+    let squashed = match kind {
+        DeploymentKind::Edit => state
+            .db
+            .list_deployments(username)?
+            .into_iter()
+            .next()
+            .filter(|d| d.is_active && d.kind == DeploymentKind::Edit),
+        DeploymentKind::Upload => None,
+    };
+
+    let deployment =
+        state
+            .db
+            .create_deployment(username, size_bytes, file_count, uploaded_by, kind)?;
+
+    let release_dir = release_dir(state, username, deployment.id);
+    let result = move_dir(staged, &release_dir)
+        .map_err(AppError::from)
+        .and_then(|_| activate_release(state, username, &release_dir));
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(&release_dir);
+        let _ = std::fs::remove_dir_all(compress::sidecar_dir(&release_dir));
+        state.db.delete_deployment(deployment.id)?;
+        return Err(e);
+    }
+
+    state
+        .db
+        .set_active_deployment(username, Some(deployment.id))?;
+    if let Some(squashed) = squashed {
+        remove_release(state, username, &squashed)?;
+    }
+    prune_releases(state, username)?;
+    if state.config.precompress {
+        compress::precompress_release(release_dir);
+    }
+
+    Ok(Deployment {
+        is_active: true,
+        ..deployment
+    })
+}
+
+fn release_dir(state: &AppState, username: &str, id: i64) -> PathBuf {
+    state
+        .config
+        .releases_dir()
+        .join(username)
+        .join(id.to_string())
+}
+
+/// Point the live site at a release by atomically swapping the `sites/{username}` symlink.
+fn activate_release(
+    state: &AppState,
+    username: &str,
+    release_dir: &StdPath,
+) -> Result<(), AppError> {
+    let site_dir = state.config.sites_dir().join(username);
+
+    // Relative target so the data directory can be moved or mounted elsewhere
+    let target =
+        PathBuf::from("..").join(release_dir.strip_prefix(&state.config.data_dir).map_err(
+            |_| {
+                AppError::Internal(format!(
+                    "Release outside data dir: {}",
+                    release_dir.display()
+                ))
+            },
+        )?);
+
+    let link_tmp = site_dir.with_extension(format!("link-{}", rand::random::<u32>()));
+    std::os::unix::fs::symlink(&target, &link_tmp)?;
+
+    // A plain directory (new account or a site from before releases) can't be renamed over,
+    // so move it out of the way first. This is the only non-atomic step and happens once.
+    let is_plain_dir = std::fs::symlink_metadata(&site_dir)
+        .map(|m| m.is_dir())
+        .unwrap_or(false);
+    if is_plain_dir {
+        let old_dir = site_dir.with_extension("old");
+        let _ = std::fs::remove_dir_all(&old_dir);
+        std::fs::rename(&site_dir, &old_dir)?;
+        let result = std::fs::rename(&link_tmp, &site_dir);
+        let _ = std::fs::remove_dir_all(&old_dir);
+        return result.map_err(AppError::from);
+    }
+
+    std::fs::rename(&link_tmp, &site_dir).inspect_err(|_| {
+        let _ = std::fs::remove_file(&link_tmp);
+    })?;
+    Ok(())
+}
+
+/// Stage a copy of the live site that can be edited and published as a new release, leaving
+/// the release it came from untouched. Files are hard links into the live release where the
+/// filesystem allows, so they must be replaced by renaming over them, never written in place.
+pub fn stage_live_site(state: &AppState, username: &str) -> Result<tempfile::TempDir, AppError> {
+    let staged = tempfile::tempdir_in(state.config.tmp_dir())
+        .map_err(|e| AppError::Internal(format!("Failed to create temp dir: {e}")))?;
+    let live = state.config.sites_dir().join(username);
+    if live.is_dir() {
+        link_dir_recursive(&live, staged.path())?;
+    }
+    Ok(staged)
+}
+
+/// Replace the live site with an empty directory.
+pub fn clear_live_site(state: &AppState, username: &str) -> Result<(), AppError> {
+    let site_dir = state.config.sites_dir().join(username);
+    match std::fs::symlink_metadata(&site_dir) {
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(&site_dir)?,
+        Ok(_) => std::fs::remove_file(&site_dir)?,
+        Err(_) => {}
+    }
+    std::fs::create_dir_all(&site_dir)?;
+    Ok(())
+}
+
+/// Drop releases beyond the configured retention count, then the oldest past releases once
+/// they take up more than one disk quota. Only the space a past release adds is counted:
+/// files it shares with the live release or a newer one through hard links count once. The
+/// live release is always kept.
+fn prune_releases(state: &AppState, username: &str) -> Result<(), AppError> {
+    for deployment in state
+        .db
+        .expired_deployments(username, state.config.deploy_retention)?
+    {
+        remove_release(state, username, &deployment)?;
+    }
+
+    let deployments = state.db.list_deployments(username)?;
+    let mut seen = HashSet::new();
+    if let Some(active) = deployments.iter().find(|d| d.is_active) {
+        disk_usage(&release_dir(state, username, active.id), &mut seen)?;
+    }
+    let mut retained_bytes = 0u64;
+    for deployment in deployments.iter().filter(|d| !d.is_active) {
+        if retained_bytes <= state.config.disk_quota_bytes {
+            let dir = release_dir(state, username, deployment.id);
+            retained_bytes += disk_usage(&dir, &mut seen)?;
+        }
+        if retained_bytes > state.config.disk_quota_bytes {
+            remove_release(state, username, deployment)?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete a release's files and then its record. A release whose files can't be removed is
+/// left in place, so it is tried again on the next prune.
+fn remove_release(
+    state: &AppState,
+    username: &str,
+    deployment: &Deployment,
+) -> Result<(), AppError> {
+    let dir = release_dir(state, username, deployment.id);
+    let _ = std::fs::remove_dir_all(compress::sidecar_dir(&dir));
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("Failed to remove release {}: {e}", dir.display());
+            return Ok(());
+        }
+    }
+    state.db.delete_deployment(deployment.id)
+}
+
+/// Bytes taken by the files under `dir` whose inodes aren't in `seen` yet, adding them.
+fn disk_usage(dir: &StdPath, seen: &mut HashSet<(u64, u64)>) -> Result<u64, std::io::Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut total = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            total += disk_usage(&entry.path(), seen)?;
+        } else if seen.insert((metadata.dev(), metadata.ino())) {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+fn move_dir(src: &StdPath, dst: &StdPath) -> Result<(), std::io::Error> {
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(src, dst).or_else(|_| {
+        // Cross-device rename fallback: copy recursively
+        copy_dir_recursive(src, dst)
+    })
+}
+
+fn link_dir_recursive(src: &StdPath, dst: &StdPath) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_dir_recursive(&entry.path(), &target)?;
+        } else if std::fs::hard_link(entry.path(), &target).is_err() {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_dir_recursive(src: &StdPath, dst: &StdPath) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_recursive(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Custom domains for user sites. A domain is added with a random token and only routes to
+//! the site once the server has fetched that token back from
+//! `http://{domain}/.well-known/simple-pages/{token}`, which proves the domain points here.
+//! The server's own host names can never be claimed.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper_util::rt::TokioIo;
+use std::time::Duration;
+
+use crate::auth::{AdminUser, AuthUser};
+use crate::db::CustomDomain;
+use crate::error::AppError;
+use crate::AppState;
+
+pub const WELL_KNOWN_PREFIX: &str = "/.well-known/simple-pages/";
+
+const MAX_DOMAINS_PER_USER: usize = 5;
+
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(serde::Deserialize)]
+pub struct AddDomainRequest {
+    pub domain: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AdminAddDomainRequest {
+    pub username: String,
+    pub domain: String,
+}
+
+pub async fn list_domains(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let domains = state.db.list_custom_domains(Some(&auth.username))?;
+    Ok(Json(serde_json::json!({ "domains": domains })))
+}
+
+pub async fn add_domain(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AddDomainRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let domain = create_domain(&state, &headers, &auth.username, &req.domain)?;
+    Ok(Json(serde_json::json!({ "domain": domain })))
+}
+
+pub async fn verify_domain(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let domain = owned_domain(&state, &auth.username, &domain)?;
+    if domain.verified_at.is_none() {
+        check_domain(&domain.domain, &domain.token).await?;
+        state.db.mark_domain_verified(&domain.domain, "token")?;
+    }
+    let domain = owned_domain(&state, &auth.username, &domain.domain)?;
+    Ok(Json(serde_json::json!({ "domain": domain })))
+}
+
+pub async fn remove_domain(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let domain = owned_domain(&state, &auth.username, &domain)?;
+    state.db.delete_custom_domain(&domain.domain)?;
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+pub async fn admin_list_domains(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let domains = state.db.list_custom_domains(None)?;
+    Ok(Json(serde_json::json!({ "domains": domains })))
+}
+
+pub async fn admin_add_domain(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<AdminAddDomainRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let username = req.username.trim().to_lowercase();
+    if state.db.get_user_by_username(&username)?.is_none() {
+        return Err(AppError::NotFound(format!("No user named {username}")));
+    }
+    let domain = create_domain(&state, &headers, &username, &req.domain)?;
+    Ok(Json(serde_json::json!({ "domain": domain })))
+}
+
+/// Admins vouch for a domain themselves, so this skips the token check.
+pub async fn admin_verify_domain(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let domain = normalize_domain(&domain)?;
+    if state.config.is_platform_host(&domain) {
+        return Err(AppError::BadRequest(format!(
+            "{domain} is part of this server's own domains"
+        )));
+    }
+    state.db.mark_domain_verified(&domain, "admin")?;
+    let domain = state
+        .db
+        .get_custom_domain(&domain)?
+        .ok_or_else(|| AppError::NotFound("Domain not found".to_string()))?;
+    Ok(Json(serde_json::json!({ "domain": domain })))
+}
+
+pub async fn admin_remove_domain(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if !state.db.delete_custom_domain(&normalize_domain(&domain)?)? {
+        return Err(AppError::NotFound("Domain not found".to_string()));
+    }
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// Answer the verification request for a domain that has been added but may not be
+/// verified yet. Only answered on the domain itself, and never on the server's own hosts, so
+/// the token can't be fetched back through a host the user doesn't point here. `None` if
+/// the path isn't a verification URL.
+pub fn well_known_response(state: &AppState, domain: &str, path: &str) -> Option<Response> {
+    let token = path.strip_prefix(WELL_KNOWN_PREFIX)?;
+    let record = state
+        .db
+        .get_custom_domain(domain)
+        .ok()
+        .flatten()
+        .filter(|_| !state.config.is_platform_host(domain));
+    Some(match record {
+        Some(record) if record.token == token => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            record.token,
+        )
+            .into_response(),
+        _ => crate::error::error_page(StatusCode::NOT_FOUND),
+    })
+}
+
+/// The `Host` header without its port, lowercase.
+pub fn request_domain(headers: &HeaderMap) -> Option<String> {
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let domain = match host.rsplit_once(':') {
+        Some((domain, port)) if port.chars().all(|c| c.is_ascii_digit()) => domain,
+        _ => host,
+    };
+    Some(domain.trim_end_matches('.').to_ascii_lowercase())
+}
+
+fn create_domain(
+    state: &AppState,
+    headers: &HeaderMap,
+    username: &str,
+    raw: &str,
+) -> Result<CustomDomain, AppError> {
+    let domain = normalize_domain(raw)?;
+    // The host this request came in on is the management host, even when it isn't configured
+    let own_host = request_domain(headers)
+        .is_some_and(|host| domain == host || domain.ends_with(&format!(".{host}")));
+    if own_host || state.config.is_platform_host(&domain) {
+        return Err(AppError::BadRequest(format!(
+            "{domain} is part of this server's own domains"
+        )));
+    }
+
+    if state.db.list_custom_domains(Some(username))?.len() >= MAX_DOMAINS_PER_USER {
+        return Err(AppError::BadRequest(format!(
+            "A site can have at most {MAX_DOMAINS_PER_USER} custom domains"
+        )));
+    }
+
+    let token = format!("{:032x}", rand::random::<u128>());
+    state.db.add_custom_domain(username, &domain, &token)
+}
+
+fn owned_domain(state: &AppState, username: &str, domain: &str) -> Result<CustomDomain, AppError> {
+    state
+        .db
+        .get_custom_domain(&normalize_domain(domain)?)?
+        .filter(|d| d.username == username)
+        .ok_or_else(|| AppError::NotFound("Domain not found".to_string()))
+}
+
+/// Lowercase a host name and check it is a plausible public DNS name.
+fn normalize_domain(raw: &str) -> Result<String, AppError> {
+    let domain = raw.trim().trim_end_matches('.').to_ascii_lowercase();
+    let invalid = || AppError::BadRequest(format!("{} is not a valid domain name", raw.trim()));
+
+    if domain.len() > 253 {
+        return Err(invalid());
+    }
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    };
+    // Needs at least a name and a TLD, and a TLD is never all digits (that's an IP address)
+    let tld_ok = labels
+        .last()
+        .is_some_and(|tld| !tld.chars().all(|c| c.is_ascii_digit()));
+    if labels.len() < 2 || !labels.iter().all(valid_label) || !tld_ok {
+        return Err(invalid());
+    }
+    Ok(domain)
+}
+
+/// Fetch the verification URL over plain HTTP and check it returns the token.
+async fn check_domain(domain: &str, token: &str) -> Result<(), AppError> {
+    let path = format!("{WELL_KNOWN_PREFIX}{token}");
+    let url = format!("http://{domain}{path}");
+
+    let fetch = async {
+        let stream = tokio::net::TcpStream::connect((domain, 80)).await?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+
+        let request = Request::get(path.as_str())
+            .header(header::HOST, domain)
+            .body(Empty::<Bytes>::new())?;
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        let body = Limited::new(response.into_body(), 1024)
+            .collect()
+            .await?
+            .to_bytes();
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>((status, body))
+    };
+
+    match tokio::time::timeout(VERIFY_TIMEOUT, fetch).await {
+        Err(_) => Err(AppError::BadRequest(format!("Timed out fetching {url}"))),
+        Ok(Err(e)) => Err(AppError::BadRequest(format!("Could not fetch {url}: {e}"))),
+        Ok(Ok((status, body)))
+            if status == StatusCode::OK && body.trim_ascii() == token.as_bytes() =>
+        {
+            Ok(())
+        }
+        Ok(Ok((status, _))) => Err(AppError::BadRequest(format!(
+            "{url} answered {status} without the verification token. \
+             Check that the domain's DNS points to this server."
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_domain_lowercases_and_trims() {
+        assert_eq!(
+            normalize_domain("Robotics.School.AC.th").unwrap(),
+            "robotics.school.ac.th"
+        );
+        assert_eq!(normalize_domain("  example.com.  ").unwrap(), "example.com");
+        assert_eq!(
+            normalize_domain("xn--12c1fe0br.com").unwrap(),
+            "xn--12c1fe0br.com"
+        );
+        assert_eq!(normalize_domain("a-b.c-d.io").unwrap(), "a-b.c-d.io");
+        assert_eq!(normalize_domain("123.example").unwrap(), "123.example");
+    }
+
+    #[test]
+    fn normalize_domain_needs_a_name_and_a_tld() {
+        for raw in ["", ".", "localhost", "example.", ".com", "example..com"] {
+            assert!(normalize_domain(raw).is_err(), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn normalize_domain_rejects_ip_addresses() {
+        for raw in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "192.168.1.20",
+            "[::1]",
+            "::1",
+            "1.2.3.4.",
+        ] {
+            assert!(normalize_domain(raw).is_err(), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn normalize_domain_rejects_invalid_labels() {
+        for raw in [
+            "-example.com",
+            "example-.com",
+            "exa_mple.com",
+            "exa mple.com",
+            "example.com:8080",
+            "https://example.com",
+            "example.com/path",
+            "*.example.com",
+            "เว็บ.com",
+        ] {
+            assert!(normalize_domain(raw).is_err(), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn normalize_domain_enforces_length_limits() {
+        let label = "a".repeat(63);
+        assert!(normalize_domain(&format!("{label}.com")).is_ok());
+        assert!(normalize_domain(&format!("a{label}.com")).is_err());
+
+        let long = format!("{label}.{label}.{label}.{}.com", "a".repeat(57));
+        assert_eq!(long.len(), 253);
+        assert!(normalize_domain(&long).is_ok());
+        assert!(normalize_domain(&format!("a{long}")).is_err());
+    }
+
+    #[test]
+    fn normalize_domain_reports_the_input() {
+        let message = normalize_domain(" not a domain ")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            message.ends_with("not a domain is not a valid domain name"),
+            "{message}"
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
+use axum::response::{Html, IntoResponse, Response};
 
 #[derive(Debug)]
 pub enum AppError {
@@ -26,9 +26,17 @@ impl std::fmt::Display for AppError {
     }
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message) = match &self {
+#[derive(askama::Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    status: u16,
+    title: &'static str,
+    description: &'static str,
+}
+
+impl AppError {
+    fn status_and_message(&self) -> (StatusCode, String) {
+        match self {
             Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
             Self::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
             Self::Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
@@ -39,8 +47,36 @@ impl IntoResponse for AppError {
                 tracing::error!("Internal error: {msg}");
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
             }
-        };
+        }
+    }
 
+    /// The styled error page shown to site visitors. API callers get JSON instead.
+    pub fn into_html_response(self) -> Response {
+        let (status, _) = self.status_and_message();
+        error_page(status)
+    }
+}
+
+pub fn error_page(status: StatusCode) -> Response {
+    let description = match status {
+        StatusCode::NOT_FOUND => "The page you're looking for doesn't exist or has been moved.",
+        StatusCode::FORBIDDEN => "You don't have permission to view this page.",
+        StatusCode::INTERNAL_SERVER_ERROR => {
+            "Something went wrong on our side. Please try again later."
+        }
+        _ => "The request couldn't be completed.",
+    };
+    let template = ErrorTemplate {
+        status: status.as_u16(),
+        title: status.canonical_reason().unwrap_or("Error"),
+        description,
+    };
+    (status, Html(template.to_string())).into_response()
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, message) = self.status_and_message();
         let body = serde_json::json!({ "error": message });
         (status, axum::Json(body)).into_response()
     }
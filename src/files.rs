@@ -0,0 +1,246 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::path::{Path as StdPath, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+use crate::archive;
+use crate::auth::AuthUser;
+use crate::db::{Deployment, DeploymentKind};
+use crate::deploy::{self, DeployGuard};
+use crate::error::AppError;
+use crate::rules;
+use crate::site;
+use crate::AppState;
+
+#[derive(serde::Deserialize)]
+pub struct MoveRequest {
+    pub from: String,
+    pub to: String,
+}
+
+pub async fn get_file(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+) -> Result<Response, AppError> {
+    let site_root = state.config.sites_dir().join(&auth.username);
+    let file_path = resolve(&state, &site_root, &path)?;
+    if !file_path.is_file() {
+        return Err(AppError::NotFound("File not found".to_string()));
+    }
+
+    let data = tokio::fs::read(&file_path).await?;
+    let mime =
+        site::mime_from_extension(file_path.extension().and_then(|e| e.to_str()).unwrap_or(""));
+
+    // This is the management origin: never let an uploaded page run here
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, mime),
+            (header::CACHE_CONTROL, "no-store"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (header::CONTENT_SECURITY_POLICY, "sandbox"),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+/// Create or replace one file with the raw request body.
+pub async fn put_file(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    body: Body,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let guard = state.deploy_locks.acquire(&auth.username)?;
+    let staged = deploy::stage_live_site(&state, &auth.username)?;
+    let site_root = staged.path();
+    let file_path = resolve(&state, site_root, &path)?;
+
+    if file_path.is_dir() {
+        return Err(AppError::Conflict(format!("{path} is a folder")));
+    }
+    ensure_parent_dirs(site_root, &file_path)?;
+
+    // Everything else on the site plus this file must fit in the quota
+    let (_, usage) = site::list_files_recursive(site_root)?;
+    let existing = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+    let allowance = state
+        .config
+        .disk_quota_bytes
+        .saturating_sub(usage - existing);
+
+    // Rename into place rather than writing through the hard link into the live release
+    let temp = tempfile::NamedTempFile::new_in(state.config.tmp_dir())?;
+    let mut out = tokio::fs::File::from_std(temp.reopen()?);
+    let mut written = 0u64;
+    let mut stream = body.into_data_stream();
+
+    while let Some(chunk) = futures_util::StreamExt::next(&mut stream).await {
+        let chunk =
+            chunk.map_err(|e| AppError::BadRequest(format!("Failed to read upload: {e}")))?;
+        written += chunk.len() as u64;
+        if written > allowance {
+            return Err(AppError::PayloadTooLarge(format!(
+                "File would exceed disk quota of {} MB",
+                state.config.disk_quota_bytes / (1024 * 1024)
+            )));
+        }
+        out.write_all(&chunk).await?;
+    }
+    out.flush().await?;
+    drop(out);
+
+    if let Ok(relative) = file_path.strip_prefix(site_root) {
+        if rules::is_rules_file(relative) {
+            rules::validate_file(relative, &std::fs::read(temp.path())?)?;
+        }
+    }
+
+    temp.persist(&file_path)
+        .map_err(|e| AppError::Internal(format!("Failed to save file: {e}")))?;
+    let (deployment, usage) = publish_edit(&state, &guard, &auth.username, staged)?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "path": path,
+        "size": written,
+        "disk_usage_bytes": usage,
+        "deployment": deployment,
+    })))
+}
+
+/// Delete a file, or a folder with everything in it.
+pub async fn delete_file(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let guard = state.deploy_locks.acquire(&auth.username)?;
+    let staged = deploy::stage_live_site(&state, &auth.username)?;
+    let file_path = resolve(&state, staged.path(), &path)?;
+
+    if file_path.is_dir() {
+        std::fs::remove_dir_all(&file_path)?;
+    } else if file_path.is_file() {
+        std::fs::remove_file(&file_path)?;
+    } else {
+        return Err(AppError::NotFound("File not found".to_string()));
+    }
+    let (deployment, usage) = publish_edit(&state, &guard, &auth.username, staged)?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "disk_usage_bytes": usage,
+        "deployment": deployment,
+    })))
+}
+
+/// Move or rename a file or folder within the site.
+pub async fn move_file(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(req): Json<MoveRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let guard = state.deploy_locks.acquire(&auth.username)?;
+    let staged = deploy::stage_live_site(&state, &auth.username)?;
+    let site_root = staged.path();
+    let from = resolve(&state, site_root, &req.from)?;
+    let to = resolve(&state, site_root, &req.to)?;
+
+    if !from.exists() {
+        return Err(AppError::NotFound("File not found".to_string()));
+    }
+    if to.exists() {
+        return Err(AppError::Conflict(format!("{} already exists", req.to)));
+    }
+    if to.starts_with(&from) {
+        return Err(AppError::BadRequest(
+            "Cannot move a folder into itself".to_string(),
+        ));
+    }
+
+    if let Ok(relative) = to.strip_prefix(site_root) {
+        if rules::is_rules_file(relative) && from.is_file() {
+            rules::validate_file(relative, &std::fs::read(&from)?)?;
+        }
+    }
+
+    ensure_parent_dirs(site_root, &to)?;
+    std::fs::rename(&from, &to)?;
+    let (deployment, _) = publish_edit(&state, &guard, &auth.username, staged)?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "path": req.to,
+        "deployment": deployment,
+    })))
+}
+
+/// Publish an edited copy of the live site as a new release, so retained releases keep the
+/// exact files they were deployed with and rollback restores them. Consecutive edits share
+/// one release and don't count toward the retained uploads.
+fn publish_edit(
+    state: &AppState,
+    guard: &DeployGuard,
+    username: &str,
+    staged: tempfile::TempDir,
+) -> Result<(Deployment, u64), AppError> {
+    let (files, total_size) = site::list_files_recursive(staged.path())?;
+    let deployment = deploy::publish_release_locked(
+        guard,
+        state,
+        username,
+        username,
+        staged.path(),
+        total_size,
+        files.len() as u64,
+        DeploymentKind::Edit,
+    )?;
+    Ok((deployment, total_size))
+}
+
+/// Map a user-supplied path onto a site directory, applying the same rules as archive uploads.
+fn resolve(state: &AppState, site_root: &StdPath, raw: &str) -> Result<PathBuf, AppError> {
+    let relative = archive::sanitize_archive_path(raw)?;
+    if relative == StdPath::new(".") {
+        return Err(AppError::BadRequest("A file path is required".to_string()));
+    }
+    archive::check_path_shape(
+        &relative,
+        state.config.max_path_depth,
+        state.config.max_path_length,
+    )?;
+
+    Ok(site_root.join(relative))
+}
+
+fn ensure_parent_dirs(site_root: &StdPath, file_path: &StdPath) -> Result<(), AppError> {
+    let Some(parent) = file_path.parent() else {
+        return Ok(());
+    };
+
+    // A file sitting where a folder is needed would otherwise surface as an opaque I/O error
+    for ancestor in parent
+        .ancestors()
+        .take_while(|a| a.starts_with(site_root) && *a != site_root)
+    {
+        if ancestor.exists() && !ancestor.is_dir() {
+            let name = ancestor.strip_prefix(site_root).unwrap_or(ancestor);
+            return Err(AppError::Conflict(format!(
+                "{} is a file, not a folder",
+                name.display()
+            )));
+        }
+    }
+
+    std::fs::create_dir_all(parent)?;
+    Ok(())
+}
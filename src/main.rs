@@ -1,17 +1,35 @@
+mod access;
 mod admin;
+mod archive;
 mod auth;
+mod autoindex;
+mod cache;
+mod compress;
 mod config;
 mod db;
+mod deploy;
+mod domains;
 mod error;
+mod files;
+mod range;
+mod rewrite;
+mod rules;
+mod settings;
 mod site;
+mod tls;
+mod tus;
 
 use axum::{
-    extract::State,
-    response::{Html, IntoResponse, Redirect},
-    routing::{delete, get, post},
-    Router,
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{any, delete, get, head, options, post, put},
+    Router, ServiceExt,
 };
 use std::net::SocketAddr;
+use tower::Layer;
+use tower_http::compression::CompressionLayer;
 use tower_http::limit::RequestBodyLimitLayer;
 
 use config::Config;
@@ -21,6 +39,9 @@ use db::Db;
 pub struct AppState {
     pub config: Config,
     pub db: Db,
+    pub deploy_locks: deploy::DeployLocks,
+    pub site_rules: rules::RuleCache,
+    pub unlock_limiter: access::UnlockLimiter,
 }
 
 
@@ -42,6 +63,7 @@ struct RegisterTemplate {
 #[template(path = "dashboard.html")]
 struct DashboardTemplate {
     username: String,
+    site_url: String,
 }
 
 #[derive(askama::Template)]
@@ -58,8 +80,7 @@ async fn page_index(
     if let Some(cookie) = headers.get(axum::http::header::COOKIE) {
         if let Ok(cookie_str) = cookie.to_str() {
             if let Some(token) = cookie_str.split(';').find_map(|c| {
-                let c = c.trim();
-                c.starts_with("token=").then(|| c.trim_start_matches("token="))
+                c.trim().strip_prefix(auth::session_cookie_name(&state.config))?.strip_prefix('=')
             }) {
                 if let Ok(claims) = auth::decode_token(token, &state.config.jwt_secret) {
                     if claims.is_admin {
@@ -80,8 +101,12 @@ async fn page_register() -> impl IntoResponse {
     Html(template.to_string())
 }
 
-async fn page_dashboard(user: auth::AuthUser) -> impl IntoResponse {
+async fn page_dashboard(
+    State(state): State<AppState>,
+    user: auth::AuthUser,
+) -> impl IntoResponse {
     let template = DashboardTemplate {
+        site_url: state.config.site_url(&user.username),
         username: user.username,
     };
     Html(template.to_string())
@@ -92,6 +117,196 @@ async fn page_admin(_admin: auth::AdminUser) -> impl IntoResponse {
     Html(template.to_string())
 }
 
+// Unknown paths: JSON for API callers, the styled error page for everyone else
+async fn not_found(uri: axum::http::Uri) -> impl IntoResponse {
+    if uri.path().starts_with("/api/") {
+        return error::AppError::NotFound("Not found".to_string()).into_response();
+    }
+    error::error_page(axum::http::StatusCode::NOT_FOUND)
+}
+
+// --- Host-based routing ---
+
+fn is_content_host(config: &Config, headers: &HeaderMap) -> bool {
+    let Some(content_host) = config.content_host() else {
+        return false;
+    };
+    headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|host| host.eq_ignore_ascii_case(content_host))
+}
+
+// The management UI and API never answer on the content origin, so a script on a hosted
+// site can't reach them as a same-origin request
+async fn management_host_only(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_content_host(&state.config, request.headers()) {
+        return not_found(request.uri().clone()).await.into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether a browser marked the request as coming from another origin. Sibling subdomains,
+/// such as the content origin or user subdomains, count as the same site, so `SameSite`
+/// cookies alone don't keep their pages from posting forms to the API.
+fn is_cross_origin(headers: &HeaderMap) -> bool {
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return !matches!(site.as_bytes(), b"same-origin" | b"none");
+    }
+    // Without either header the request didn't come from a browser page
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return false;
+    };
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    let origin_host = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, host)| host);
+    match (origin_host, host) {
+        (Some(origin_host), Some(host)) => !origin_host.eq_ignore_ascii_case(host),
+        _ => true,
+    }
+}
+
+// Anything that changes state must come from the management UI's own pages
+async fn same_origin_writes(request: Request, next: Next) -> Response {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if !safe && is_cross_origin(request.headers()) {
+        return error::AppError::Forbidden("Cross-origin requests are not allowed".to_string())
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// The username for a request to `{username}.{subdomain host}`. `Err` for a subdomain
+/// that can't be a username, `Ok(None)` for any other host.
+fn subdomain_username(config: &Config, headers: &HeaderMap) -> Result<Option<String>, ()> {
+    let Some(base) = config.subdomain_host() else {
+        return Ok(None);
+    };
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let Some(label) = host
+        .strip_suffix(&base.to_ascii_lowercase())
+        .and_then(|rest| rest.strip_suffix('.'))
+    else {
+        return Ok(None);
+    };
+    match auth::validate_username(label) {
+        Ok(()) if !label.contains('.') && config.user_subdomain(label).is_some() => {
+            Ok(Some(label.to_string()))
+        }
+        _ => Err(()),
+    }
+}
+
+// Requests to a user's subdomain or custom domain are rewritten onto that user's path routes
+// before routing, so the rest of the app serves them like any other site request
+async fn route_by_host(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let username = match subdomain_username(&state.config, request.headers()) {
+        Ok(Some(username)) => username,
+        Ok(None) => {
+            // The server's own hosts stay with the management UI whatever the database says
+            let Some(domain) = domains::request_domain(request.headers())
+                .filter(|domain| !state.config.is_platform_host(domain))
+            else {
+                return next.run(request).await;
+            };
+            if let Some(response) =
+                domains::well_known_response(&state, &domain, request.uri().path())
+            {
+                return response;
+            }
+            match state.db.verified_domain_owner(&domain) {
+                Ok(Some(username)) => username,
+                _ => return next.run(request).await,
+            }
+        }
+        Err(()) => return error::error_page(StatusCode::NOT_FOUND),
+    };
+
+    let path = request.uri().path_and_query().map_or("/", |p| p.as_str());
+    let Ok(uri) = format!("/{username}{path}").parse() else {
+        return error::error_page(StatusCode::BAD_REQUEST);
+    };
+    *request.uri_mut() = uri;
+    request.extensions_mut().insert(site::SiteHost);
+    next.run(request).await
+}
+
+// Path-routed site requests redirect to the site's own subdomain, or to the content origin
+// when one is configured
+async fn canonical_site_host(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<site::SiteHost>().is_some() {
+        return next.run(request).await;
+    }
+
+    let config = &state.config;
+    let uri = request.uri();
+    let query = uri.query().map(|q| format!("?{q}")).unwrap_or_default();
+    let (username, rest) = uri.path()[1..]
+        .split_once('/')
+        .unwrap_or((&uri.path()[1..], ""));
+    let subdomain = auth::validate_username(username)
+        .ok()
+        .and_then(|()| config.user_subdomain(username));
+    let location = if let Some(origin) = subdomain {
+        format!("{origin}/{rest}{query}")
+    } else if let Some(origin) = config
+        .content_origin
+        .as_ref()
+        .filter(|_| !is_content_host(config, request.headers()))
+    {
+        format!("{origin}{}{query}", uri.path())
+    } else {
+        return next.run(request).await;
+    };
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, location)],
+    )
+        .into_response()
+}
+
+// Plain HTTP requests move to HTTPS, except domain verification, which is fetched over HTTP
+async fn redirect_to_https(State(state): State<AppState>, request: Request) -> Response {
+    let Some(domain) = domains::request_domain(request.headers()) else {
+        return error::error_page(StatusCode::BAD_REQUEST);
+    };
+    if let Some(response) = domains::well_known_response(&state, &domain, request.uri().path()) {
+        return response;
+    }
+    let port = state
+        .config
+        .tls_listen_addr
+        .as_deref()
+        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+        .map(|addr| addr.port())
+        .filter(|port| *port != 443)
+        .map_or(String::new(), |port| format!(":{port}"));
+    let path = request.uri().path_and_query().map_or("/", |p| p.as_str());
+    Redirect::permanent(&format!("https://{domain}{port}{path}")).into_response()
+}
+
 // --- Static assets for management UI ---
 async fn serve_static(
     axum::extract::Path(filename): axum::extract::Path<String>,
@@ -125,18 +340,33 @@ async fn main() {
         .init();
 
     let config = Config::from_env();
+    for (name, host) in config.same_site_content_hosts() {
+        tracing::warn!(
+            "{name} host {host} shares a domain with MANAGEMENT_ORIGIN, so browsers treat \
+             student pages as the same site as the dashboard. Use a separate registrable domain."
+        );
+    }
 
     // Ensure directories exist
     std::fs::create_dir_all(config.sites_dir()).expect("Failed to create sites directory");
+    std::fs::create_dir_all(config.releases_dir()).expect("Failed to create releases directory");
+    std::fs::create_dir_all(config.tmp_dir()).expect("Failed to create temp directory");
+    std::fs::create_dir_all(config.uploads_dir()).expect("Failed to create uploads directory");
 
     let db = Db::open(&config.db_path()).expect("Failed to open database");
 
     let state = AppState {
         config: config.clone(),
         db,
+        deploy_locks: deploy::DeployLocks::default(),
+        site_rules: rules::RuleCache::default(),
+        unlock_limiter: access::UnlockLimiter::default(),
     };
 
-    let app = Router::new()
+    // Text files without a precompressed sidecar are compressed per request
+    let site_compression = CompressionLayer::new().compress_when(compress::compress_on_the_fly);
+
+    let management = Router::new()
         // Pages
         .route("/", get(page_index))
         .route("/register", get(page_register))
@@ -152,17 +382,102 @@ async fn main() {
         .route("/api/site", get(site::get_site_info))
         .route("/api/site/upload", post(site::upload_site))
         .route("/api/site", delete(site::delete_site))
+        .route("/api/site/deployments", get(deploy::list_deployments))
+        .route(
+            "/api/site/deployments/{id}/rollback",
+            post(deploy::rollback_deployment),
+        )
+        .route(
+            "/api/site/files/{*path}",
+            get(files::get_file)
+                .put(files::put_file)
+                .delete(files::delete_file),
+        )
+        .route("/api/site/move", post(files::move_file))
+        .route(
+            "/api/site/settings",
+            get(settings::get_settings).put(settings::update_settings),
+        )
+        .route(
+            "/api/site/share",
+            post(access::create_share_link).delete(access::revoke_share_links),
+        )
+        .route("/api/sites/{username}/open", get(access::open_site))
+        .route(
+            "/api/site/password",
+            put(settings::set_password).delete(settings::clear_password),
+        )
+        .route(
+            "/api/site/tus",
+            options(tus::options).post(tus::create_upload),
+        )
+        .route(
+            "/api/site/tus/{id}",
+            head(tus::upload_status)
+                .patch(tus::append_chunk)
+                .delete(tus::delete_upload),
+        )
+        .route(
+            "/api/site/domains",
+            get(domains::list_domains).post(domains::add_domain),
+        )
+        .route("/api/site/domains/{domain}", delete(domains::remove_domain))
+        .route(
+            "/api/site/domains/{domain}/verify",
+            post(domains::verify_domain),
+        )
         // Admin API
         .route("/api/admin/codes", get(admin::list_codes))
         .route("/api/admin/codes", post(admin::generate_codes))
         .route("/api/admin/codes/{code}", delete(admin::revoke_code))
-        // User sites — must be last (catch-all)
-        .route("/{username}/", get(site::serve_user_site_index))
-        .route("/{username}/{*path}", get(site::serve_user_site))
+        .route(
+            "/api/admin/domains",
+            get(domains::admin_list_domains).post(domains::admin_add_domain),
+        )
+        .route(
+            "/api/admin/domains/{domain}",
+            delete(domains::admin_remove_domain),
+        )
+        .route(
+            "/api/admin/domains/{domain}/verify",
+            post(domains::admin_verify_domain),
+        )
+        // Keep unknown API paths away from the user-site catch-all
+        .route("/api/{*rest}", any(not_found))
+        .route_layer(middleware::from_fn(same_origin_writes))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            management_host_only,
+        ));
+
+    // User sites (catch-all; matched after the fixed routes above)
+    let sites = Router::new()
+        .route("/{username}", get(site::redirect_to_site))
+        .route(
+            "/{username}/",
+            get(site::serve_user_site_index)
+                .post(access::unlock_site)
+                .layer(site_compression.clone()),
+        )
+        .route(
+            "/{username}/{*path}",
+            get(site::serve_user_site)
+                .post(access::unlock_site)
+                .layer(site_compression),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            canonical_site_host,
+        ));
+
+    let app = management
+        .merge(sites)
+        .fallback(not_found)
         .layer(RequestBodyLimitLayer::new(
             config.max_upload_bytes as usize + 1024, // small overhead for multipart headers
         ))
-        .with_state(state);
+        .with_state(state.clone());
+    let app = middleware::from_fn_with_state(state.clone(), route_by_host).layer(app);
 
     let addr: SocketAddr = config.listen_addr.parse().expect("Invalid listen address");
     tracing::info!("Listening on {addr}");
@@ -171,5 +486,21 @@ async fn main() {
         .await
         .expect("Failed to bind");
 
-    axum::serve(listener, app).await.expect("Server error");
+    let Some(tls_addr) = &config.tls_listen_addr else {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .expect("Server error");
+        return;
+    };
+
+    // With built-in HTTPS, plain HTTP only redirects
+    let tls_addr: SocketAddr = tls_addr.parse().expect("Invalid TLS listen address");
+    let certs = tls::CertManager::start(&state);
+    let redirect = Router::new().fallback(redirect_to_https).with_state(state);
+    tokio::spawn(async move {
+        axum::serve(listener, redirect.into_make_service())
+            .await
+            .expect("Server error");
+    });
+    tls::serve(tls_addr, certs, app).await;
 }
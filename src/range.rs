@@ -0,0 +1,264 @@
+//! `Range` request support for hosted files (RFC 9110 §14): parsing byte ranges and
+//! streaming the selected parts of a file, as `multipart/byteranges` when there are several.
+
+use axum::body::{Body, Bytes};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// More ranges than this in one request is treated as abuse and answered with the whole file.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range within a file.
+#[derive(Clone, Copy)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+pub enum RangeRequest {
+    /// No usable `Range` header; send the whole file.
+    Full,
+    Partial(Vec<ByteRange>),
+    /// Syntactically valid, but no range overlaps the file.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header against a file of `len` bytes. Malformed headers and units
+/// other than `bytes` are ignored, as the RFC requires.
+pub fn parse(header: &str, len: u64) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim) {
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = match (first.trim(), last.trim()) {
+            // Suffix range: the final N bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some(ByteRange {
+                    start: len.saturating_sub(n),
+                    end: len - 1,
+                }),
+                Ok(_) => None,
+                Err(_) => return RangeRequest::Full,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Full,
+                    },
+                };
+                (start < len).then(|| ByteRange {
+                    start,
+                    end: end.min(len - 1),
+                })
+            }
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else if ranges.len() > MAX_RANGES {
+        RangeRequest::Full
+    } else {
+        RangeRequest::Partial(ranges)
+    }
+}
+
+/// Stream `len` bytes of an open file starting at `start`.
+pub async fn file_body(
+    mut file: tokio::fs::File,
+    start: u64,
+    len: u64,
+) -> Result<Body, std::io::Error> {
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
+    Ok(Body::from_stream(ReaderStream::new(file.take(len))))
+}
+
+/// Build a `multipart/byteranges` body. Returns the boundary and the exact body length,
+/// so the response can carry a `Content-Length`.
+pub fn multipart_body(
+    path: PathBuf,
+    ranges: &[ByteRange],
+    total: u64,
+    content_type: &str,
+) -> (String, u64, Body) {
+    let boundary = format!("{:016x}", rand::random::<u64>());
+
+    let mut length = 0u64;
+    let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+    for (i, range) in ranges.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "\r\n" };
+        let head = format!(
+            "{separator}--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+            range.content_range(total)
+        );
+        length += head.len() as u64 + range.len();
+        parts.push(Part::Bytes(Bytes::from(head)));
+        parts.push(Part::File(*range));
+    }
+    let tail = format!("\r\n--{boundary}--\r\n");
+    length += tail.len() as u64;
+    parts.push(Part::Bytes(Bytes::from(tail)));
+
+    let body = stream::iter(parts).flat_map(move |part| match part {
+        Part::Bytes(bytes) => stream::once(async move { Ok(bytes) }).boxed(),
+        Part::File(range) => {
+            let path = path.clone();
+            stream::once(async move {
+                let mut file = tokio::fs::File::open(&path).await?;
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Ok::<_, std::io::Error>(ReaderStream::new(file.take(range.len())))
+            })
+            .try_flatten()
+            .boxed()
+        }
+    });
+
+    (boundary, length, Body::from_stream(body))
+}
+
+enum Part {
+    Bytes(Bytes),
+    File(ByteRange),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(header: &str, len: u64) -> Result<Vec<(u64, u64)>, &'static str> {
+        match parse(header, len) {
+            RangeRequest::Full => Err("full"),
+            RangeRequest::Unsatisfiable => Err("unsatisfiable"),
+            RangeRequest::Partial(ranges) => Ok(ranges.iter().map(|r| (r.start, r.end)).collect()),
+        }
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parsed("bytes=0-99", 1000), Ok(vec![(0, 99)]));
+        assert_eq!(parsed("bytes=500-", 1000), Ok(vec![(500, 999)]));
+        assert_eq!(parsed("bytes=-100", 1000), Ok(vec![(900, 999)]));
+        assert_eq!(parsed(" bytes= 10 - 20 ", 1000), Ok(vec![(10, 20)]));
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(parsed("bytes=900-5000", 1000), Ok(vec![(900, 999)]));
+        assert_eq!(parsed("bytes=-5000", 1000), Ok(vec![(0, 999)]));
+        assert_eq!(parsed("bytes=999-999", 1000), Ok(vec![(999, 999)]));
+    }
+
+    #[test]
+    fn several_ranges_keep_their_order() {
+        assert_eq!(
+            parsed("bytes=0-0, 10-19, -1", 100),
+            Ok(vec![(0, 0), (10, 19), (99, 99)])
+        );
+    }
+
+    #[test]
+    fn ranges_outside_the_file_are_unsatisfiable() {
+        assert_eq!(parsed("bytes=1000-", 1000), Err("unsatisfiable"));
+        assert_eq!(parsed("bytes=1000-1999", 1000), Err("unsatisfiable"));
+        assert_eq!(parsed("bytes=-0", 1000), Err("unsatisfiable"));
+        assert_eq!(parsed("bytes=0-", 0), Err("unsatisfiable"));
+        assert_eq!(parsed("bytes=-10", 0), Err("unsatisfiable"));
+        // Ranges that miss are dropped as long as one overlaps
+        assert_eq!(parsed("bytes=2000-, 0-9", 1000), Ok(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn malformed_headers_get_the_whole_file() {
+        for header in [
+            "",
+            "bytes=",
+            "items=0-9",
+            "bytes=abc",
+            "bytes=9-0",
+            "bytes=0-x",
+            "bytes=x-9",
+            "bytes=-x",
+            "bytes=0-9,,",
+            "bytes=--1",
+        ] {
+            assert_eq!(parsed(header, 1000), Err("full"), "{header}");
+        }
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_whole_file() {
+        let specs: Vec<String> = (0..=MAX_RANGES as u64)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect();
+        let header = format!("bytes={}", specs.join(","));
+        assert_eq!(parsed(&header, 1000), Err("full"));
+
+        let header = format!("bytes={}", specs[..MAX_RANGES].join(","));
+        assert_eq!(parsed(&header, 1000).map(|r| r.len()), Ok(MAX_RANGES));
+    }
+
+    #[test]
+    fn content_range_and_len() {
+        let range = ByteRange { start: 10, end: 19 };
+        assert_eq!(range.len(), 10);
+        assert_eq!(range.content_range(100), "bytes 10-19/100");
+    }
+
+    #[tokio::test]
+    async fn file_body_streams_the_requested_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let body = file_body(file, 3, 4).await.unwrap();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"3456");
+    }
+
+    #[tokio::test]
+    async fn multipart_body_matches_its_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        let ranges = [ByteRange { start: 0, end: 1 }, ByteRange { start: 8, end: 9 }];
+        let (boundary, length, body) = multipart_body(path, &ranges, 10, "text/plain");
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(bytes.len() as u64, length);
+
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{boundary}--\r\n"
+        );
+        assert_eq!(std::str::from_utf8(&bytes).unwrap(), expected);
+    }
+}
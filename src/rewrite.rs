@@ -0,0 +1,337 @@
+//! Optional rewriting of root-relative URLs for sites served under `/{username}/`. Students
+//! write `href="/style.css"` as if their site had a host to itself; with rewriting on, pages
+//! and stylesheets get such URLs prefixed with the site's base path as they are served.
+//! External and relative URLs are left alone, and so are inline scripts, which can't be
+//! rewritten safely.
+
+use lol_html::html_content::ContentType;
+use lol_html::{element, text, HtmlRewriter, Settings};
+use std::io::Read;
+use std::path::Path;
+
+/// Larger files are served as they are, since the rewritten copy is held in memory.
+pub const MAX_SIZE: u64 = 2 * 1024 * 1024;
+
+/// How much of a page is fed to the rewriter at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Whether a file with this extension and size is rewritten.
+pub fn applies(ext: &str, len: u64) -> bool {
+    matches!(ext.to_ascii_lowercase().as_str(), "html" | "htm" | "css") && len <= MAX_SIZE
+}
+
+/// Rewrite an HTML page or stylesheet for a site at `base`. Anything that can't be parsed is
+/// served as it is. This reads the file and parses it synchronously, so run it on a
+/// blocking thread.
+pub fn rewrite_file(path: &Path, ext: &str, base: &str) -> std::io::Result<Vec<u8>> {
+    if ext.eq_ignore_ascii_case("css") {
+        let content = std::fs::read(path)?;
+        return Ok(match std::str::from_utf8(&content) {
+            Ok(css) => rewrite_css(css, base).into_bytes(),
+            Err(_) => content,
+        });
+    }
+    match rewrite_html(std::fs::File::open(path)?, base)? {
+        Some(output) => Ok(output),
+        None => std::fs::read(path),
+    }
+}
+
+/// Stream a page through the rewriter a chunk at a time. `None` if it can't be parsed.
+fn rewrite_html(mut input: impl Read, base: &str) -> std::io::Result<Option<Vec<u8>>> {
+    let mut output = Vec::new();
+    let mut style = String::new();
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: vec![
+                element!("[href], [src]", |el| {
+                    for name in ["href", "src"] {
+                        let rewritten = el.get_attribute(name).and_then(|v| prefixed(&v, base));
+                        if let Some(url) = rewritten {
+                            el.set_attribute(name, &url)?;
+                        }
+                    }
+                    Ok(())
+                }),
+                element!("[srcset]", |el| {
+                    if let Some(srcset) = el.get_attribute("srcset") {
+                        el.set_attribute("srcset", &rewrite_srcset(&srcset, base))?;
+                    }
+                    Ok(())
+                }),
+                element!("[style]", |el| {
+                    if let Some(css) = el.get_attribute("style") {
+                        el.set_attribute("style", &rewrite_css(&css, base))?;
+                    }
+                    Ok(())
+                }),
+                // A style element's text can arrive in several chunks; hold it until the end
+                text!("style", |chunk| {
+                    style.push_str(chunk.as_str());
+                    if chunk.last_in_text_node() {
+                        chunk.replace(&rewrite_css(&style, base), ContentType::Html);
+                        style.clear();
+                    } else {
+                        chunk.remove();
+                    }
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+        |bytes: &[u8]| output.extend_from_slice(bytes),
+    );
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if rewriter.write(&buf[..read]).is_err() {
+            return Ok(None);
+        }
+    }
+    if rewriter.end().is_err() {
+        return Ok(None);
+    }
+    Ok(Some(output))
+}
+
+/// Prefix the root-relative `url(...)` references in a stylesheet.
+fn rewrite_css(css: &str, base: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest
+        .as_bytes()
+        .windows(4)
+        .position(|w| w.eq_ignore_ascii_case(b"url("))
+    {
+        let (before, after) = rest.split_at(start + 4);
+        out.push_str(before);
+        let arg = after.trim_start();
+        out.push_str(&after[..after.len() - arg.len()]);
+
+        let (quote, url_start) = match arg.chars().next() {
+            Some(q @ ('"' | '\'')) => (Some(q), &arg[1..]),
+            _ => (None, arg),
+        };
+        let end = match quote {
+            Some(q) => url_start.find(q),
+            None => url_start.find(|c: char| c == ')' || c.is_whitespace()),
+        }
+        .unwrap_or(url_start.len());
+        let url = &url_start[..end];
+
+        if let Some(q) = quote {
+            out.push(q);
+        }
+        match prefixed(url, base) {
+            Some(rewritten) => out.push_str(&rewritten),
+            None => out.push_str(url),
+        }
+        rest = &url_start[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `srcset` is a comma-separated list of URLs, each optionally followed by a descriptor.
+fn rewrite_srcset(srcset: &str, base: &str) -> String {
+    srcset
+        .split(',')
+        .map(|candidate| {
+            let trimmed = candidate.trim_start();
+            let leading = &candidate[..candidate.len() - trimmed.len()];
+            let (url, descriptor) = trimmed
+                .split_once(char::is_whitespace)
+                .map_or((trimmed, None), |(url, d)| (url, Some(d)));
+            let url = prefixed(url, base).unwrap_or_else(|| url.to_string());
+            match descriptor {
+                Some(d) => format!("{leading}{url} {d}"),
+                None => format!("{leading}{url}"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The URL with the site's base path in front, if it is root-relative. Protocol-relative
+/// URLs (`//cdn.example/x.js`) point at other hosts, and URLs already under `base` are
+/// correct as they are.
+fn prefixed(url: &str, base: &str) -> Option<String> {
+    let path = url.strip_prefix('/')?;
+    if path.starts_with(['/', '\\']) || url.starts_with(base) || url == base.trim_end_matches('/') {
+        return None;
+    }
+    Some(format!("{base}{path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "/alice/";
+
+    fn html(input: impl Read) -> String {
+        let output = rewrite_html(input, BASE).unwrap().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Hands out a few bytes per read, so the rewriter sees the page in many small chunks.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn css_url_quoted_and_unquoted() {
+        assert_eq!(
+            rewrite_css(r#"a{background:url("/a.png")}"#, BASE),
+            r#"a{background:url("/alice/a.png")}"#
+        );
+        assert_eq!(
+            rewrite_css("a{background:url('/a.png')}", BASE),
+            "a{background:url('/alice/a.png')}"
+        );
+        assert_eq!(
+            rewrite_css("a{background:url(/a.png)}", BASE),
+            "a{background:url(/alice/a.png)}"
+        );
+        assert_eq!(
+            rewrite_css("a{background:URL( /a.png )}", BASE),
+            "a{background:URL( /alice/a.png )}"
+        );
+    }
+
+    #[test]
+    fn css_several_urls() {
+        assert_eq!(
+            rewrite_css("@font-face{src:url(/f.woff2),url('/f.woff')}", BASE),
+            "@font-face{src:url(/alice/f.woff2),url('/alice/f.woff')}"
+        );
+    }
+
+    #[test]
+    fn css_leaves_other_urls_alone() {
+        for css in [
+            "a{background:url(a.png)}",
+            "a{background:url(../a.png)}",
+            "a{background:url(https://cdn.example/a.png)}",
+            "a{background:url(data:image/png;base64,AAAA)}",
+            "a{background:url(//cdn.example/a.png)}",
+            "a{background:url(/alice/a.png)}",
+            "a{background:url(\"unterminated",
+        ] {
+            assert_eq!(rewrite_css(css, BASE), css);
+        }
+    }
+
+    #[test]
+    fn protocol_relative_urls_are_left_alone() {
+        assert_eq!(prefixed("//cdn.example/x.js", BASE), None);
+        assert_eq!(prefixed("/\\cdn.example/x.js", BASE), None);
+        assert_eq!(
+            html(r#"<script src="//cdn.example/x.js"></script>"#.as_bytes()),
+            r#"<script src="//cdn.example/x.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn already_prefixed_paths_are_left_alone() {
+        assert_eq!(prefixed("/alice/style.css", BASE), None);
+        assert_eq!(prefixed("/alice", BASE), None);
+        assert_eq!(prefixed("/", BASE).as_deref(), Some("/alice/"));
+        assert_eq!(
+            prefixed("/alicexyz/a.css", BASE).as_deref(),
+            Some("/alice/alicexyz/a.css")
+        );
+        assert_eq!(
+            html(r#"<a href="/alice/about.html">About</a>"#.as_bytes()),
+            r#"<a href="/alice/about.html">About</a>"#
+        );
+    }
+
+    #[test]
+    fn href_and_src_are_prefixed() {
+        assert_eq!(
+            html(r#"<link href="/style.css"><img src="/a.png" alt="">"#.as_bytes()),
+            r#"<link href="/alice/style.css"><img src="/alice/a.png" alt="">"#
+        );
+        assert_eq!(
+            html(r#"<a href="about.html">x</a><a href="https://example.com/">y</a>"#.as_bytes()),
+            r#"<a href="about.html">x</a><a href="https://example.com/">y</a>"#
+        );
+    }
+
+    #[test]
+    fn srcset_keeps_descriptors() {
+        assert_eq!(
+            rewrite_srcset("/a.png 1x, /b.png 2x,c.png 3x", BASE),
+            "/alice/a.png 1x, /alice/b.png 2x,c.png 3x"
+        );
+        assert_eq!(
+            rewrite_srcset("/small.jpg 480w, /large.jpg", BASE),
+            "/alice/small.jpg 480w, /alice/large.jpg"
+        );
+        assert_eq!(
+            html(r#"<img srcset="/a.png 1x, //cdn.example/b.png 2x">"#.as_bytes()),
+            r#"<img srcset="/alice/a.png 1x, //cdn.example/b.png 2x">"#
+        );
+    }
+
+    #[test]
+    fn style_attributes_and_elements() {
+        assert_eq!(
+            html(r#"<div style="background:url('/a.png')"></div>"#.as_bytes()),
+            r#"<div style="background:url('/alice/a.png')"></div>"#
+        );
+        assert_eq!(
+            html("<style>body{background:url(/bg.png)}</style>".as_bytes()),
+            "<style>body{background:url(/alice/bg.png)}</style>"
+        );
+    }
+
+    #[test]
+    fn style_split_across_chunks() {
+        let page = "<head><style>body{background:url(/bg.png)} .a{background:url('/a.png')}\
+                    </style></head><body><img src=\"/x.png\"></body>";
+        assert_eq!(
+            html(Trickle(page.as_bytes())),
+            "<head><style>body{background:url(/alice/bg.png)} \
+             .a{background:url('/alice/a.png')}</style></head><body><img src=\"/alice/x.png\">\
+             </body>"
+        );
+    }
+
+    #[test]
+    fn inline_scripts_are_left_alone() {
+        let page = r#"<script>fetch("/api/data"); var s = '<a href="/x">';</script>"#;
+        assert_eq!(html(page.as_bytes()), page);
+    }
+
+    #[test]
+    fn large_and_other_files_are_not_rewritten() {
+        assert!(applies("html", 1024));
+        assert!(applies("CSS", MAX_SIZE));
+        assert!(!applies("html", MAX_SIZE + 1));
+        assert!(!applies("js", 1024));
+    }
+
+    #[test]
+    fn stylesheets_that_are_not_utf8_are_served_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("style.css");
+        let css = b"a{background:url(/a.png)}\xff".to_vec();
+        std::fs::write(&path, &css).unwrap();
+        assert_eq!(rewrite_file(&path, "css", BASE).unwrap(), css);
+    }
+}
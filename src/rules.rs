@@ -0,0 +1,530 @@
+//! Netlify-style `_redirects` and `_headers` files at the site root.
+//!
+//! Both files are parsed when a site is deployed, so syntax errors are reported to the
+//! uploader instead of silently breaking the live site. At serve time the parsed rules are
+//! cached per site and reloaded whenever the live release or either file changes.
+
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::error::AppError;
+
+pub const REDIRECTS_FILE: &str = "_redirects";
+pub const HEADERS_FILE: &str = "_headers";
+
+/// Headers a site may not set: they would break framing or reach beyond the site.
+const FORBIDDEN_HEADERS: &[&str] = &[
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "keep-alive",
+    "set-cookie",
+    "transfer-encoding",
+];
+
+#[derive(Default)]
+pub struct SiteRules {
+    redirects: Vec<RedirectRule>,
+    headers: Vec<HeaderRule>,
+}
+
+pub struct RedirectRule {
+    from: Pattern,
+    to: String,
+    pub status: StatusCode,
+    /// `!` after the status: apply even when a file exists at the path.
+    pub force: bool,
+}
+
+pub struct HeaderRule {
+    pattern: Pattern,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+/// A redirect rule matched against a request, with placeholders filled in.
+pub struct MatchedRedirect<'a> {
+    pub rule: &'a RedirectRule,
+    pub target: String,
+}
+
+impl SiteRules {
+    /// Parse the rule files in a site directory. Missing files mean no rules.
+    pub fn load(site_root: &Path) -> Result<Self, AppError> {
+        let mut rules = SiteRules::default();
+        if let Some(text) = read_optional(&site_root.join(REDIRECTS_FILE))? {
+            rules.redirects = parse_redirects(&text)?;
+        }
+        if let Some(text) = read_optional(&site_root.join(HEADERS_FILE))? {
+            rules.headers = parse_headers(&text)?;
+        }
+        Ok(rules)
+    }
+
+    /// The first redirect rule matching `path`, top to bottom.
+    pub fn find_redirect(&self, path: &str) -> Option<MatchedRedirect<'_>> {
+        self.redirects.iter().find_map(|rule| {
+            let captures = rule.from.matches(path)?;
+            Some(MatchedRedirect {
+                rule,
+                target: substitute(&rule.to, &captures),
+            })
+        })
+    }
+
+    /// Custom headers for `path`, from every matching block in file order.
+    pub fn headers_for(&self, path: &str) -> Vec<(HeaderName, HeaderValue)> {
+        self.headers
+            .iter()
+            .filter(|rule| rule.pattern.matches(path).is_some())
+            .flat_map(|rule| rule.headers.iter().cloned())
+            .collect()
+    }
+}
+
+/// Whether a site-relative path names one of the rule files.
+pub fn is_rules_file(relative: &Path) -> bool {
+    relative == Path::new(REDIRECTS_FILE) || relative == Path::new(HEADERS_FILE)
+}
+
+/// Check a file about to be written into a site; anything but a rule file passes.
+pub fn validate_file(relative: &Path, contents: &[u8]) -> Result<(), AppError> {
+    if !is_rules_file(relative) {
+        return Ok(());
+    }
+    let text = std::str::from_utf8(contents).map_err(|_| {
+        AppError::BadRequest(format!("{} is not valid UTF-8 text", relative.display()))
+    })?;
+    if relative == Path::new(REDIRECTS_FILE) {
+        parse_redirects(text)?;
+    } else {
+        parse_headers(text)?;
+    }
+    Ok(())
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>, AppError> {
+    match std::fs::read(path) {
+        Ok(data) => String::from_utf8(data).map(Some).map_err(|_| {
+            AppError::BadRequest(format!(
+                "{} is not valid UTF-8 text",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ))
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn syntax_error(file: &str, line: usize, message: impl std::fmt::Display) -> AppError {
+    AppError::BadRequest(format!("{file} line {line}: {message}"))
+}
+
+/// `from to [status][!]`, one rule per line. Lines starting with `#` are comments.
+fn parse_redirects(text: &str) -> Result<Vec<RedirectRule>, AppError> {
+    let mut rules = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| syntax_error(REDIRECTS_FILE, line_no, message);
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (from, to, status) = match fields.as_slice() {
+            [_, condition, ..] if condition.contains('=') && !condition.contains('/') => {
+                return Err(error("Query parameter conditions are not supported"))
+            }
+            [from, to] => (*from, *to, None),
+            [from, to, status] => (*from, *to, Some(*status)),
+            [_] => return Err(error("Expected a destination after the path")),
+            _ => return Err(error("Expected `from to [status]`")),
+        };
+
+        let from = Pattern::parse(from).map_err(|e| error(&e))?;
+        let (status, force) = match status {
+            None => (StatusCode::MOVED_PERMANENTLY, false),
+            Some(status) => {
+                let (code, force) = match status.strip_suffix('!') {
+                    Some(code) => (code, true),
+                    None => (status, false),
+                };
+                let status = code
+                    .parse::<u16>()
+                    .ok()
+                    .and_then(|c| StatusCode::from_u16(c).ok())
+                    .filter(|s| {
+                        matches!(s.as_u16(), 200 | 301 | 302 | 303 | 307 | 308 | 404 | 410)
+                    })
+                    .ok_or_else(|| {
+                        error("Status must be one of 200, 301, 302, 303, 307, 308, 404 or 410")
+                    })?;
+                (status, force)
+            }
+        };
+
+        let external = to.starts_with("http://") || to.starts_with("https://");
+        if !external && !to.starts_with('/') {
+            return Err(error("Destination must start with / or be an http(s) URL"));
+        }
+        if external && !status.is_redirection() {
+            return Err(error("Only redirects (3xx) may point to another site"));
+        }
+
+        rules.push(RedirectRule {
+            from,
+            to: to.to_string(),
+            status,
+            force,
+        });
+    }
+    Ok(rules)
+}
+
+/// A path pattern on its own line, followed by indented `Name: value` lines.
+fn parse_headers(text: &str) -> Result<Vec<HeaderRule>, AppError> {
+    let mut rules: Vec<HeaderRule> = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| syntax_error(HEADERS_FILE, line_no, message);
+
+        if !raw.starts_with(char::is_whitespace) {
+            let pattern = Pattern::parse(line).map_err(|e| error(&e))?;
+            rules.push(HeaderRule {
+                pattern,
+                headers: Vec::new(),
+            });
+            continue;
+        }
+
+        let rule = rules
+            .last_mut()
+            .ok_or_else(|| error("Header given before any path"))?;
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| error("Expected `Name: value`"))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| error("Invalid header name"))?;
+        if FORBIDDEN_HEADERS.contains(&name.as_str()) {
+            return Err(error(&format!("The {name} header can't be set")));
+        }
+        let value =
+            HeaderValue::from_str(value.trim()).map_err(|_| error("Invalid header value"))?;
+        rule.headers.push((name, value));
+    }
+    Ok(rules)
+}
+
+/// A site-relative path pattern: literal segments, `:name` placeholders matching one
+/// segment, and an optional trailing `*` matching the rest of the path.
+struct Pattern {
+    segments: Vec<Segment>,
+    splat: bool,
+}
+
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+impl Pattern {
+    fn parse(raw: &str) -> Result<Self, String> {
+        if !raw.starts_with('/') {
+            return Err(format!("Path must start with /: {raw}"));
+        }
+
+        let parts: Vec<&str> = raw.split('/').filter(|s| !s.is_empty()).collect();
+        let mut segments = Vec::new();
+        let mut splat = false;
+        for (i, part) in parts.iter().enumerate() {
+            if *part == "*" {
+                if i != parts.len() - 1 {
+                    return Err("* is only allowed at the end of a path".to_string());
+                }
+                splat = true;
+            } else if part.contains('*') {
+                return Err("* must be a whole path segment".to_string());
+            } else if let Some(name) = part.strip_prefix(':') {
+                if !is_placeholder_name(name) {
+                    return Err(format!("Invalid placeholder: {part}"));
+                }
+                segments.push(Segment::Placeholder(name.to_string()));
+            } else {
+                segments.push(Segment::Literal(part.to_string()));
+            }
+        }
+        Ok(Self { segments, splat })
+    }
+
+    /// Match a request path, ignoring a trailing slash. Returns the captured placeholders,
+    /// with the splat under `splat`.
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if parts.len() < self.segments.len() || (!self.splat && parts.len() != self.segments.len())
+        {
+            return None;
+        }
+
+        let mut captures = HashMap::new();
+        for (segment, part) in self.segments.iter().zip(&parts) {
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Placeholder(name) => {
+                    captures.insert(name.clone(), part.to_string());
+                }
+            }
+        }
+        if self.splat {
+            captures.insert("splat".to_string(), parts[self.segments.len()..].join("/"));
+        }
+        Some(captures)
+    }
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace `:name` in a destination with captured values. Unknown names are left as is.
+fn substitute(target: &str, captures: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(target.len());
+    let mut rest = target;
+    while let Some(pos) = rest.find(':') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        match captures.get(&after[..len]).filter(|_| is_placeholder_name(&after[..len])) {
+            Some(value) => out.push_str(value),
+            None => {
+                out.push(':');
+                out.push_str(&after[..len]);
+            }
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parsed rules per site, reparsed when the live release or a rule file changes.
+#[derive(Clone, Default)]
+pub struct RuleCache {
+    entries: Arc<Mutex<HashMap<String, CachedRules>>>,
+}
+
+struct CachedRules {
+    key: CacheKey,
+    rules: Arc<SiteRules>,
+}
+
+#[derive(PartialEq)]
+struct CacheKey {
+    site_root: PathBuf,
+    redirects_modified: Option<SystemTime>,
+    headers_modified: Option<SystemTime>,
+}
+
+impl RuleCache {
+    pub fn get(&self, username: &str, site_root: &Path) -> Arc<SiteRules> {
+        let modified = |name| {
+            std::fs::metadata(site_root.join(name))
+                .and_then(|m| m.modified())
+                .ok()
+        };
+        let key = CacheKey {
+            site_root: site_root.to_path_buf(),
+            redirects_modified: modified(REDIRECTS_FILE),
+            headers_modified: modified(HEADERS_FILE),
+        };
+
+        if let Some(cached) = self.entries.lock().unwrap().get(username) {
+            if cached.key == key {
+                return cached.rules.clone();
+            }
+        }
+
+        // Files are validated on the way in, so this only fails for sites deployed before
+        // rules existed; serve them without rules rather than not at all
+        let rules = Arc::new(SiteRules::load(site_root).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid rules for {username}: {e}");
+            SiteRules::default()
+        }));
+        self.entries.lock().unwrap().insert(
+            username.to_string(),
+            CachedRules {
+                key,
+                rules: rules.clone(),
+            },
+        );
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirects(text: &str) -> SiteRules {
+        SiteRules {
+            redirects: parse_redirects(text).unwrap(),
+            headers: Vec::new(),
+        }
+    }
+
+    fn headers(text: &str) -> SiteRules {
+        SiteRules {
+            redirects: Vec::new(),
+            headers: parse_headers(text).unwrap(),
+        }
+    }
+
+    fn redirects_error(text: &str) -> String {
+        parse_redirects(text).err().unwrap().to_string()
+    }
+
+    fn headers_error(text: &str) -> String {
+        parse_headers(text).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn redirect_defaults_to_301() {
+        let rules = redirects("/old /new");
+        let matched = rules.find_redirect("/old").unwrap();
+        assert_eq!(matched.target, "/new");
+        assert_eq!(matched.rule.status, StatusCode::MOVED_PERMANENTLY);
+        assert!(!matched.rule.force);
+        assert!(rules.find_redirect("/older").is_none());
+    }
+
+    #[test]
+    fn redirect_status_and_force() {
+        let rules = redirects("/a /b 302\n/c /d 200!\n/e https://example.com/ 308");
+        let matched = rules.find_redirect("/a").unwrap();
+        assert_eq!(matched.rule.status, StatusCode::FOUND);
+        assert!(!matched.rule.force);
+        let matched = rules.find_redirect("/c").unwrap();
+        assert_eq!(matched.rule.status, StatusCode::OK);
+        assert!(matched.rule.force);
+        let matched = rules.find_redirect("/e").unwrap();
+        assert_eq!(matched.target, "https://example.com/");
+        assert_eq!(matched.rule.status, StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let rules = redirects("# moved\n\n   \n  /a /b  \n# /c /d");
+        assert!(rules.find_redirect("/a").is_some());
+        assert!(rules.find_redirect("/c").is_none());
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = redirects("/blog/* /news/:splat\n/blog/first /elsewhere");
+        assert_eq!(rules.find_redirect("/blog/first").unwrap().target, "/news/first");
+    }
+
+    #[test]
+    fn placeholders_and_splat() {
+        let rules = redirects("/users/:name/posts/* /u/:name/:splat 302");
+        let matched = rules.find_redirect("/users/alice/posts/2024/hello").unwrap();
+        assert_eq!(matched.target, "/u/alice/2024/hello");
+        assert!(rules.find_redirect("/users/alice").is_none());
+
+        // The splat may be empty
+        let rules = redirects("/docs/* /manual/:splat");
+        assert_eq!(rules.find_redirect("/docs").unwrap().target, "/manual/");
+        assert_eq!(rules.find_redirect("/docs/").unwrap().target, "/manual/");
+    }
+
+    #[test]
+    fn trailing_slashes_are_ignored() {
+        let rules = redirects("/about/ /about-us");
+        assert!(rules.find_redirect("/about").is_some());
+        assert!(rules.find_redirect("/about/").is_some());
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_in_the_target() {
+        let rules = redirects("/a/:id /b/:id/:other");
+        assert_eq!(rules.find_redirect("/a/7").unwrap().target, "/b/7/:other");
+    }
+
+    #[test]
+    fn substitute_keeps_urls_with_colons() {
+        let captures = HashMap::from([("id".to_string(), "7".to_string())]);
+        assert_eq!(
+            substitute("https://example.com:8443/x/:id", &captures),
+            "https://example.com:8443/x/7"
+        );
+        assert_eq!(substitute("/a/:id-:id", &captures), "/a/7-7");
+    }
+
+    #[test]
+    fn invalid_redirects_report_their_line() {
+        assert!(redirects_error("/a /b\n/c").contains("_redirects line 2"));
+        assert!(redirects_error("/a /b 418").contains("Status must be one of"));
+        assert!(redirects_error("/a /b 200 extra").contains("Expected `from to [status]`"));
+        assert!(redirects_error("/a b").contains("Destination must start with /"));
+        assert!(redirects_error("a /b").contains("Path must start with /"));
+        assert!(redirects_error("/a https://example.com/ 200").contains("Only redirects"));
+        assert!(redirects_error("/a id=1 /b").contains("Query parameter conditions"));
+        assert!(redirects_error("/*/a /b").contains("only allowed at the end"));
+        assert!(redirects_error("/a* /b").contains("whole path segment"));
+        assert!(redirects_error("/:1st /b").contains("Invalid placeholder"));
+    }
+
+    #[test]
+    fn headers_apply_from_every_matching_block() {
+        let rules = headers(
+            "/*\n  X-Frame-Options: DENY\n/assets/*\n  Cache-Control: public, max-age=60\n",
+        );
+        let names = |path| {
+            rules
+                .headers_for(path)
+                .into_iter()
+                .map(|(name, value)| format!("{name}: {}", value.to_str().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("/index.html"), ["x-frame-options: DENY"]);
+        assert_eq!(
+            names("/assets/app.js"),
+            ["x-frame-options: DENY", "cache-control: public, max-age=60"]
+        );
+    }
+
+    #[test]
+    fn invalid_headers_report_their_line() {
+        assert!(headers_error("  X-A: b").contains("Header given before any path"));
+        assert!(headers_error("/*\n  X-A").contains("_headers line 2: Expected `Name: value`"));
+        assert!(headers_error("/*\n  Bad Name: b").contains("Invalid header name"));
+        assert!(headers_error("/*\n  Set-Cookie: a=b").contains("can't be set"));
+        assert!(headers_error("/*\n  Content-Length: 1").contains("can't be set"));
+        assert!(headers_error("/*\n  X-A: \u{7f}").contains("Invalid header value"));
+    }
+
+    #[test]
+    fn only_rule_files_are_validated() {
+        assert!(is_rules_file(Path::new("_redirects")));
+        assert!(is_rules_file(Path::new("_headers")));
+        assert!(!is_rules_file(Path::new("docs/_redirects")));
+        assert!(validate_file(Path::new("notes.txt"), b"\xff").is_ok());
+        assert!(validate_file(Path::new("_redirects"), b"\xff").is_err());
+        assert!(validate_file(Path::new("_redirects"), b"/a /b").is_ok());
+        assert!(validate_file(Path::new("_headers"), b"  X-A: b").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use axum::{extract::State, Json};
+
+use crate::auth::{self, AuthUser};
+use crate::db::{SiteSettings, Visibility};
+use crate::error::AppError;
+use crate::AppState;
+
+/// Fields left out of the request keep their current value.
+#[derive(serde::Deserialize)]
+pub struct SettingsUpdate {
+    pub spa_fallback: Option<bool>,
+    pub clean_urls: Option<bool>,
+    pub autoindex: Option<bool>,
+    pub rewrite_urls: Option<bool>,
+    pub visibility: Option<Visibility>,
+}
+
+pub async fn get_settings(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<SiteSettings>, AppError> {
+    Ok(Json(state.db.get_site_settings(&auth.username)?))
+}
+
+pub async fn update_settings(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(update): Json<SettingsUpdate>,
+) -> Result<Json<SiteSettings>, AppError> {
+    let mut settings = state.db.get_site_settings(&auth.username)?;
+    if let Some(spa_fallback) = update.spa_fallback {
+        settings.spa_fallback = spa_fallback;
+    }
+    if let Some(clean_urls) = update.clean_urls {
+        settings.clean_urls = clean_urls;
+    }
+    if let Some(autoindex) = update.autoindex {
+        settings.autoindex = autoindex;
+    }
+    if let Some(rewrite_urls) = update.rewrite_urls {
+        settings.rewrite_urls = rewrite_urls;
+    }
+    if let Some(visibility) = update.visibility {
+        settings.visibility = visibility;
+    }
+    state.db.save_site_settings(&auth.username, &settings)?;
+    Ok(Json(settings))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordUpdate {
+    pub password: String,
+}
+
+/// Require visitors to enter a password before any page of the site is served.
+pub async fn set_password(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(update): Json<PasswordUpdate>,
+) -> Result<Json<SiteSettings>, AppError> {
+    if update.password.is_empty() {
+        return Err(AppError::BadRequest("Password can't be empty".to_string()));
+    }
+    let hash = auth::hash_password(&update.password)?;
+    state.db.set_site_password(&auth.username, Some(&hash))?;
+    Ok(Json(state.db.get_site_settings(&auth.username)?))
+}
+
+pub async fn clear_password(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<SiteSettings>, AppError> {
+    state.db.set_site_password(&auth.username, None)?;
+    Ok(Json(state.db.get_site_settings(&auth.username)?))
+}
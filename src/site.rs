@@ -1,13 +1,27 @@
 use axum::{
-    extract::{Multipart, Path, State},
-    http::{header, StatusCode},
-    response::{IntoResponse, Response},
+    extract::{multipart::Field, Multipart, Path, Query, RawQuery, State},
+    Extension,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
     Json,
 };
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
 use std::path::{Path as StdPath, PathBuf};
+use tokio::io::AsyncWriteExt;
 
+use crate::access::{self, SiteAccess};
+use crate::archive::{self, ArchiveFormat, ExtractLimits};
 use crate::auth::AuthUser;
-use crate::error::AppError;
+use crate::autoindex;
+use crate::cache::{self, Validators};
+use crate::compress;
+use crate::db::{Deployment, SiteSettings, Visibility};
+use crate::deploy;
+use crate::error::{self, AppError};
+use crate::range::{self, RangeRequest};
+use crate::rewrite;
+use crate::rules::{self, MatchedRedirect};
 use crate::AppState;
 
 #[derive(serde::Serialize)]
@@ -19,12 +33,32 @@ pub struct SiteInfo {
     pub site_url: String,
 }
 
+/// Marks a request routed to a site by its `Host` header rather than by path. The site is
+/// then at the root of the host instead of under `/{username}/`.
+#[derive(Clone)]
+pub struct SiteHost;
+
 #[derive(serde::Serialize)]
 pub struct FileEntry {
     pub path: String,
     pub size: u64,
 }
 
+#[derive(serde::Deserialize)]
+pub struct UploadParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What publishing a staged upload would do to the live site.
+#[derive(serde::Serialize)]
+pub struct SiteDiff {
+    pub added: Vec<FileEntry>,
+    pub removed: Vec<FileEntry>,
+    pub changed: Vec<FileEntry>,
+    pub unchanged_count: usize,
+}
+
 pub async fn get_site_info(
     auth: AuthUser,
     State(state): State<AppState>,
@@ -33,7 +67,7 @@ pub async fn get_site_info(
     let (files, total_size) = list_files_recursive(&site_dir)?;
 
     Ok(Json(SiteInfo {
-        site_url: format!("/{}/", auth.username),
+        site_url: state.config.site_url(&auth.username),
         username: auth.username,
         disk_usage_bytes: total_size,
         quota_bytes: state.config.disk_quota_bytes,
@@ -41,14 +75,19 @@ pub async fn get_site_info(
     }))
 }
 
+/// Deploy an upload. With `?dry_run=true` the upload is extracted and checked the same way,
+/// but only the diff against the live site is returned and nothing is published.
 pub async fn upload_site(
     auth: AuthUser,
     State(state): State<AppState>,
+    Query(params): Query<UploadParams>,
     mut multipart: Multipart,
 ) -> Result<Response, AppError> {
-    let mut archive_data: Option<(String, Vec<u8>)> = None;
+    let mut uploads: Vec<(String, tempfile::NamedTempFile)> = Vec::new();
+    let mut received = 0u64;
 
-    while let Some(field) = multipart
+    // Either one archive, or any number of plain files named by their relative paths
+    while let Some(mut field) = multipart
         .next_field()
         .await
         .map_err(|e| AppError::BadRequest(format!("Multipart error: {e}")))?
@@ -63,88 +102,196 @@ pub async fn upload_site(
             .unwrap_or("upload")
             .to_string();
 
-        let data = field
-            .bytes()
-            .await
-            .map_err(|e| AppError::BadRequest(format!("Failed to read upload: {e}")))?;
+        let file = save_field_to_temp(&mut field, &state, &mut received).await?;
+        uploads.push((filename, file));
+    }
 
-        if data.len() as u64 > state.config.max_upload_bytes {
-            return Err(AppError::PayloadTooLarge(format!(
-                "File exceeds maximum upload size of {} MB",
-                state.config.max_upload_bytes / (1024 * 1024)
-            )));
-        }
+    if uploads.is_empty() {
+        return Err(AppError::BadRequest("No file uploaded".to_string()));
+    }
+
+    // Extraction, hashing and publishing all hit the disk, so keep them off the async workers
+    let dry_run = params.dry_run;
+    let body = tokio::task::spawn_blocking(move || {
+        deploy_upload(&state, &auth.username, &uploads, received, dry_run)
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("Upload failed: {e}")))??;
+
+    Ok((StatusCode::OK, Json(body)).into_response())
+}
 
-        archive_data = Some((filename, data.to_vec()));
-        break;
+/// Stage an upload and publish it, or with `dry_run` only describe what it would change.
+fn deploy_upload(
+    state: &AppState,
+    username: &str,
+    uploads: &[(String, tempfile::NamedTempFile)],
+    received: u64,
+    dry_run: bool,
+) -> Result<serde_json::Value, AppError> {
+    let files: Vec<_> = uploads
+        .iter()
+        .map(|(name, file)| (name.clone(), file.path()))
+        .collect();
+    let staged = stage_upload(state, &files, received)?;
+
+    if dry_run {
+        let site_dir = state.config.sites_dir().join(username);
+        let (_, current_size) = list_files_recursive(&site_dir)?;
+        let (_, total_size) = list_files_recursive(staged.path())?;
+        let diff = diff_site(&site_dir, staged.path())?;
+
+        return Ok(serde_json::json!({
+            "success": true,
+            "dry_run": true,
+            "diff": diff,
+            "current_disk_usage_bytes": current_size,
+            "disk_usage_bytes": total_size,
+            "quota_bytes": state.config.disk_quota_bytes,
+        }));
     }
 
-    let (filename, data) = archive_data
-        .ok_or_else(|| AppError::BadRequest("No file uploaded".to_string()))?;
+    let (deployment, total_size) = publish_staged(state, username, staged)?;
 
-    // Extract to temp dir
-    let temp_dir = tempfile::tempdir()
+    Ok(serde_json::json!({
+        "success": true,
+        "site_url": state.config.site_url(username),
+        "disk_usage_bytes": total_size,
+        "deployment": deployment,
+    }))
+}
+
+/// Extract an uploaded archive, or lay out plain uploaded files, into a fresh staging
+/// directory. All path rules and size limits are enforced here.
+pub fn stage_upload(
+    state: &AppState,
+    uploads: &[(String, &StdPath)],
+    received: u64,
+) -> Result<tempfile::TempDir, AppError> {
+    // Extract to temp dir on the same filesystem, so it can be renamed into a release
+    let temp_dir = tempfile::tempdir_in(state.config.tmp_dir())
         .map_err(|e| AppError::Internal(format!("Failed to create temp dir: {e}")))?;
 
-    if filename.ends_with(".zip") {
-        extract_zip(&data, temp_dir.path())?;
-    } else if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
-        extract_tar_gz(&data, temp_dir.path())?;
+    let mut limits = ExtractLimits::new(&state.config, received);
+    let single_archive = match uploads {
+        [(filename, path)] => match ArchiveFormat::detect(path) {
+            Ok(format) => Some(format),
+            Err(e) if archive::has_archive_extension(filename) => return Err(e),
+            Err(_) => None,
+        },
+        _ => None,
+    };
+
+    if let Some(format) = single_archive {
+        archive::extract(uploads[0].1, format, temp_dir.path(), &mut limits)?;
     } else {
-        return Err(AppError::BadRequest(
-            "Unsupported format. Please upload a .zip or .tar.gz file".to_string(),
-        ));
+        archive::stage_files(uploads, temp_dir.path(), &mut limits)?;
     }
 
-    // Check extracted size against quota
-    let (_, total_size) = list_files_recursive(temp_dir.path())?;
-    if total_size > state.config.disk_quota_bytes {
-        return Err(AppError::PayloadTooLarge(format!(
-            "Extracted files ({:.1} MB) exceed disk quota of {} MB",
-            total_size as f64 / (1024.0 * 1024.0),
-            state.config.disk_quota_bytes / (1024 * 1024)
-        )));
-    }
+    // Report mistakes in _redirects and _headers now rather than on the live site
+    rules::SiteRules::load(temp_dir.path())?;
 
-    // Atomic replace: rename temp to site dir
-    let site_dir = state.config.sites_dir().join(&auth.username);
-    let old_dir = site_dir.with_extension("old");
+    Ok(temp_dir)
+}
 
-    // Remove any leftover old dir
-    let _ = std::fs::remove_dir_all(&old_dir);
+/// Keep a staged upload as a numbered release and make it live.
+pub fn publish_staged(
+    state: &AppState,
+    username: &str,
+    staged: tempfile::TempDir,
+) -> Result<(Deployment, u64), AppError> {
+    // Quota was enforced during extraction
+    let (files, total_size) = list_files_recursive(staged.path())?;
+
+    let deployment = deploy::publish_release(
+        state,
+        username,
+        username,
+        staged.path(),
+        total_size,
+        files.len() as u64,
+    )?;
+    Ok((deployment, total_size))
+}
 
-    // Move current site to .old (if exists)
-    if site_dir.exists() {
-        std::fs::rename(&site_dir, &old_dir)?;
+/// Compare the live site with a staged upload. Files present in both are compared by
+/// content hash, so re-uploading an identical file doesn't show up as a change.
+fn diff_site(live: &StdPath, staged: &StdPath) -> Result<SiteDiff, AppError> {
+    let (live_files, _) = list_files_recursive(live)?;
+    let (staged_files, _) = list_files_recursive(staged)?;
+
+    let mut live_sizes: BTreeMap<String, u64> =
+        live_files.into_iter().map(|f| (f.path, f.size)).collect();
+    let mut diff = SiteDiff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+        unchanged_count: 0,
+    };
+
+    for file in staged_files {
+        match live_sizes.remove(&file.path) {
+            None => diff.added.push(file),
+            Some(size)
+                if size != file.size
+                    || file_digest(&live.join(&file.path))?
+                        != file_digest(&staged.join(&file.path))? =>
+            {
+                diff.changed.push(file)
+            }
+            Some(_) => diff.unchanged_count += 1,
+        }
     }
+    diff.removed = live_sizes
+        .into_iter()
+        .map(|(path, size)| FileEntry { path, size })
+        .collect();
 
-    // Move extracted files to site dir
-    std::fs::rename(temp_dir.path(), &site_dir).or_else(|_| {
-        // Cross-device rename fallback: copy recursively
-        copy_dir_recursive(temp_dir.path(), &site_dir)
-    })?;
+    Ok(diff)
+}
 
-    // Clean up old dir
-    let _ = std::fs::remove_dir_all(&old_dir);
+fn file_digest(path: &StdPath) -> Result<Vec<u8>, AppError> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
 
-    let body = serde_json::json!({
-        "success": true,
-        "site_url": format!("/{}/", auth.username),
-        "disk_usage_bytes": total_size,
-    });
+/// Stream a multipart field into a temp file, enforcing the upload limit as chunks arrive.
+/// `received` carries the running total across all fields of the request.
+async fn save_field_to_temp(
+    field: &mut Field<'_>,
+    state: &AppState,
+    received: &mut u64,
+) -> Result<tempfile::NamedTempFile, AppError> {
+    let temp = tempfile::NamedTempFile::new_in(state.config.tmp_dir())?;
+    let mut out = tokio::fs::File::from_std(temp.reopen()?);
+
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Failed to read upload: {e}")))?
+    {
+        *received += chunk.len() as u64;
+        if *received > state.config.max_upload_bytes {
+            return Err(AppError::PayloadTooLarge(format!(
+                "Upload exceeds maximum size of {} MB",
+                state.config.max_upload_bytes / (1024 * 1024)
+            )));
+        }
+        out.write_all(&chunk).await?;
+    }
+    out.flush().await?;
 
-    Ok((StatusCode::OK, Json(body)).into_response())
+    Ok(temp)
 }
 
 pub async fn delete_site(
     auth: AuthUser,
     State(state): State<AppState>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    let site_dir = state.config.sites_dir().join(&auth.username);
-    if site_dir.exists() {
-        std::fs::remove_dir_all(&site_dir)?;
-        std::fs::create_dir_all(&site_dir)?;
-    }
+    let _guard = state.deploy_locks.acquire(&auth.username)?;
+    deploy::clear_live_site(&state, &auth.username)?;
+    state.db.set_active_deployment(&auth.username, None)?;
     Ok(Json(serde_json::json!({ "success": true })))
 }
 
@@ -152,19 +299,212 @@ pub async fn delete_site(
 pub async fn serve_user_site(
     State(state): State<AppState>,
     Path((username, path)): Path<(String, String)>,
-) -> Result<Response, AppError> {
-    serve_file(&state, &username, &path).await
+    RawQuery(query): RawQuery,
+    site_host: Option<Extension<SiteHost>>,
+    headers: HeaderMap,
+) -> Response {
+    let base = site_base(&username, site_host.is_some());
+    serve_site(&state, &username, &base, &path, query.as_deref(), &headers).await
 }
 
 // Serve index for /{username}/
 pub async fn serve_user_site_index(
     State(state): State<AppState>,
     Path(username): Path<String>,
+    RawQuery(query): RawQuery,
+    site_host: Option<Extension<SiteHost>>,
+    headers: HeaderMap,
+) -> Response {
+    let base = site_base(&username, site_host.is_some());
+    serve_site(&state, &username, &base, "", query.as_deref(), &headers).await
+}
+
+/// The URL path a site is mounted at, for building links and redirects back into it.
+pub fn site_base(username: &str, host_routed: bool) -> String {
+    if host_routed {
+        "/".to_string()
+    } else {
+        format!("/{username}/")
+    }
+}
+
+async fn serve_site(
+    state: &AppState,
+    username: &str,
+    base: &str,
+    path: &str,
+    query: Option<&str>,
+    headers: &HeaderMap,
+) -> Response {
+    // Loaded once and passed down; access checks need it, so a failure is fatal
+    let settings = match state.db.get_site_settings(username) {
+        Ok(settings) => settings,
+        Err(e) => return e.into_html_response(),
+    };
+    let access = access::check(state, username, &settings, base, path, query, headers);
+    if let SiteAccess::Respond(response) = access {
+        return response;
+    }
+    let settings = &settings;
+
+    let request_path = format!("/{path}");
+    let rules = site_root(state, username)
+        .ok()
+        .map(|root| state.site_rules.get(username, &root));
+
+    let result = match rules.as_ref().and_then(|r| r.find_redirect(&request_path)) {
+        Some(matched) if matched.rule.force => {
+            apply_redirect(state, username, base, &matched, query, settings, headers).await
+        }
+        // Unforced rules only apply when no file exists at the path
+        Some(matched) => {
+            match serve_path(state, username, base, path, query, settings, headers).await {
+                Err(AppError::NotFound(_)) => {
+                    apply_redirect(state, username, base, &matched, query, settings, headers)
+                        .await
+                }
+                result => result,
+            }
+        }
+        None => match serve_path(state, username, base, path, query, settings, headers).await {
+            // Client-side routes of a single-page app: let the app's own router handle them
+            Err(AppError::NotFound(_)) if settings.spa_fallback && is_app_route(path) => {
+                serve_file(state, username, base, "index.html", None, settings, headers).await
+            }
+            result => result,
+        },
+    };
+
+    let mut response = match result {
+        Ok(response) => response,
+        Err(e) => site_error(state, username, base, e, settings, headers).await,
+    };
+
+    // Custom headers from _headers; the first block setting a name replaces our default
+    if let Some(rules) = rules {
+        let mut replaced = HashSet::new();
+        for (name, value) in rules.headers_for(&request_path) {
+            if replaced.insert(name.clone()) {
+                response.headers_mut().insert(name, value);
+            } else {
+                response.headers_mut().append(name, value);
+            }
+        }
+    }
+    // Restricted pages must not be kept by shared caches
+    if matches!(access, SiteAccess::Granted) {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-cache"),
+        );
+    }
+    if settings.visibility != Visibility::Public {
+        response
+            .headers_mut()
+            .insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    }
+    response
+}
+
+/// Serve the file at `path`, trying `path.html` for extensionless paths when the site
+/// has clean URLs turned on.
+async fn serve_path(
+    state: &AppState,
+    username: &str,
+    base: &str,
+    path: &str,
+    query: Option<&str>,
+    settings: &SiteSettings,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    match serve_file(state, username, base, path, query, settings, headers).await {
+        Err(AppError::NotFound(_))
+            if settings.clean_urls
+                && is_app_route(path)
+                && !path.is_empty()
+                && !path.ends_with('/') =>
+        {
+            let path = format!("{path}.html");
+            serve_file(state, username, base, &path, query, settings, headers).await
+        }
+        result => result,
+    }
+}
+
+/// Carry out a matched `_redirects` rule: redirect, rewrite (200) or serve a page with an
+/// error status (404, 410).
+async fn apply_redirect(
+    state: &AppState,
+    username: &str,
+    base: &str,
+    matched: &MatchedRedirect<'_>,
+    query: Option<&str>,
+    settings: &SiteSettings,
+    headers: &HeaderMap,
 ) -> Result<Response, AppError> {
-    serve_file(&state, &username, "index.html").await
+    let status = matched.rule.status;
+    if status.is_redirection() {
+        // Site-relative destinations live under the site's base path
+        let mut location = match matched.target.strip_prefix('/') {
+            Some(rest) => format!("{base}{rest}"),
+            None => matched.target.clone(),
+        };
+        if let Some(query) = query.filter(|_| !location.contains('?')) {
+            location = format!("{location}?{query}");
+        }
+        return Ok((status, [(header::LOCATION, encode_location(&location))]).into_response());
+    }
+
+    let target = matched.target.split(['?', '#']).next().unwrap_or("");
+    let target = target.trim_start_matches('/');
+    if status == StatusCode::OK {
+        return serve_file(state, username, base, target, query, settings, headers).await;
+    }
+    let page = serve_page_with_status(state, username, base, target, status, settings, headers);
+    Ok(page.await.unwrap_or_else(|| error::error_page(status)))
 }
 
-async fn serve_file(state: &AppState, username: &str, path: &str) -> Result<Response, AppError> {
+/// Paths whose last segment has an extension are assets; a missing asset stays a 404.
+fn is_app_route(path: &str) -> bool {
+    !path.rsplit('/').next().unwrap_or("").contains('.')
+}
+
+/// Percent-encode what can't appear in a `Location` header (spaces, non-ASCII, controls),
+/// leaving URL syntax and existing escapes alone. Paths arrive here already decoded.
+pub fn encode_location(location: &str) -> String {
+    let mut out = String::with_capacity(location.len());
+    for byte in location.bytes() {
+        if byte.is_ascii_graphic() && !b"\"<>\\^`{|}".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+// Redirect /{username} to /{username}/ so relative links in the site resolve
+pub async fn redirect_to_site(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    RawQuery(query): RawQuery,
+) -> Response {
+    if site_root(&state, &username).is_err() {
+        return error::error_page(StatusCode::NOT_FOUND);
+    }
+    let location = match query {
+        Some(query) => format!("/{username}/?{query}"),
+        None => format!("/{username}/"),
+    };
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, encode_location(&location))],
+    )
+        .into_response()
+}
+
+/// Resolve the live release directory for a username.
+fn site_root(state: &AppState, username: &str) -> Result<PathBuf, AppError> {
     // Validate username
     if !username
         .chars()
@@ -173,53 +513,301 @@ async fn serve_file(state: &AppState, username: &str, path: &str) -> Result<Resp
         return Err(AppError::NotFound("Not found".to_string()));
     }
 
-    let sites_dir = state.config.sites_dir();
-    let file_path = sites_dir.join(username).join(path);
-
-    // Ensure the resolved path is within the user's site directory
-    let canonical_sites = sites_dir
+    // The site directory is a symlink to the live release; resolve it once
+    state
+        .config
+        .sites_dir()
+        .join(username)
         .canonicalize()
-        .unwrap_or_else(|_| sites_dir.clone());
-    let canonical_file = file_path
+        .map_err(|_| AppError::NotFound("Not found".to_string()))
+}
+
+async fn serve_file(
+    state: &AppState,
+    username: &str,
+    base: &str,
+    path: &str,
+    query: Option<&str>,
+    settings: &SiteSettings,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let site_root = site_root(state, username)?;
+    let canonical_file = site_root
+        .join(path)
         .canonicalize()
         .map_err(|_| AppError::NotFound("Not found".to_string()))?;
 
-    if !canonical_file.starts_with(canonical_sites.join(username)) {
+    // Ensure the resolved path is within the user's site directory
+    if !canonical_file.starts_with(&site_root) {
         return Err(AppError::Forbidden("Access denied".to_string()));
     }
 
+    if is_server_file(&site_root, &canonical_file) {
+        return Err(AppError::NotFound("Not found".to_string()));
+    }
+
     if canonical_file.is_dir() {
+        // Relative links in the folder's index only resolve with a trailing slash
+        if !path.is_empty() && !path.ends_with('/') {
+            let location = match query {
+                Some(query) => format!("{base}{path}/?{query}"),
+                None => format!("{base}{path}/"),
+            };
+            return Ok((
+                StatusCode::MOVED_PERMANENTLY,
+                [(header::LOCATION, encode_location(&location))],
+            )
+                .into_response());
+        }
+
         // Try index.html
         let index = canonical_file.join("index.html");
         if index.exists() {
-            return serve_static_file(&index).await;
+            let rewrite_base = rewrite_base(settings, base);
+            return serve_static_file(state, &site_root, &index, rewrite_base, headers).await;
+        }
+        if autoindex::has_marker(&canonical_file) || settings.autoindex {
+            return autoindex::render(&canonical_file, base, path, |p| {
+                is_server_file(&site_root, p)
+            });
         }
         return Err(AppError::NotFound("Not found".to_string()));
     }
 
-    serve_static_file(&canonical_file).await
+    let rewrite_base = rewrite_base(settings, base);
+    serve_static_file(state, &site_root, &canonical_file, rewrite_base, headers).await
+}
+
+/// Files that configure the server rather than being part of the site: `_redirects` and
+/// `_headers` at the root, and `_autoindex` markers anywhere.
+fn is_server_file(site_root: &StdPath, file: &StdPath) -> bool {
+    file.file_name() == Some(autoindex::MARKER_FILE.as_ref())
+        || file
+            .strip_prefix(site_root)
+            .is_ok_and(rules::is_rules_file)
+}
+
+/// The base path to prefix root-relative URLs with, when the site has rewriting turned on
+/// and isn't already at the root of its host.
+fn rewrite_base<'a>(settings: &SiteSettings, base: &'a str) -> Option<&'a str> {
+    (settings.rewrite_urls && base != "/").then_some(base)
 }
 
-async fn serve_static_file(path: &StdPath) -> Result<Response, AppError> {
-    let data = tokio::fs::read(path)
+/// Visitors get HTML errors: the site's own `404.html` when it has one, otherwise the
+/// platform error page.
+async fn site_error(
+    state: &AppState,
+    username: &str,
+    base: &str,
+    error: AppError,
+    settings: &SiteSettings,
+    headers: &HeaderMap,
+) -> Response {
+    if let AppError::NotFound(_) = error {
+        let status = StatusCode::NOT_FOUND;
+        let custom =
+            serve_page_with_status(state, username, base, "404.html", status, settings, headers);
+        if let Some(response) = custom.await {
+            return response;
+        }
+    }
+    error.into_html_response()
+}
+
+/// Serve a page from the site with an error status, or `None` if it doesn't exist.
+async fn serve_page_with_status(
+    state: &AppState,
+    username: &str,
+    base: &str,
+    page: &str,
+    status: StatusCode,
+    settings: &SiteSettings,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    let site_root = site_root(state, username).ok()?;
+    let page = site_root.join(page).canonicalize().ok()?;
+    if !page.starts_with(&site_root) || !page.is_file() {
+        return None;
+    }
+
+    // Conditional and range headers refer to the requested URL, not to this page
+    let mut page_headers = HeaderMap::new();
+    if let Some(accept) = headers.get(header::ACCEPT_ENCODING) {
+        page_headers.insert(header::ACCEPT_ENCODING, accept.clone());
+    }
+
+    let rewrite_base = rewrite_base(settings, base);
+    let mut response = serve_static_file(state, &site_root, &page, rewrite_base, &page_headers)
         .await
-        .map_err(|_| AppError::NotFound("Not found".to_string()))?;
+        .ok()?;
+    *response.status_mut() = status;
+    for name in [header::ETAG, header::LAST_MODIFIED, header::ACCEPT_RANGES] {
+        response.headers_mut().remove(name);
+    }
+    Some(response)
+}
 
-    let mime = mime_from_extension(
-        path.extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or(""),
+/// Serve one file of a site. With `rewrite_base`, pages and stylesheets have their
+/// root-relative URLs prefixed with it.
+async fn serve_static_file(
+    state: &AppState,
+    site_root: &StdPath,
+    path: &StdPath,
+    rewrite_base: Option<&str>,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let not_found = |_| AppError::NotFound("Not found".to_string());
+    let file = tokio::fs::File::open(path).await.map_err(not_found)?;
+    let metadata = file.metadata().await.map_err(not_found)?;
+    let len = metadata.len();
+
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let mime = mime_from_extension(ext);
+    let mut validators = Validators::from_metadata(&metadata);
+    let rewrite_base = rewrite_base.filter(|_| rewrite::applies(ext, len));
+    if rewrite_base.is_some() {
+        validators = validators.for_rewrite();
+    }
+
+    // Ranges always address the uncompressed file, and sidecars hold the original content
+    let compressible = compress::is_compressible(ext);
+    let sidecar = (compressible && !headers.contains_key(header::RANGE) && rewrite_base.is_none())
+        .then(|| compress::find_sidecar(site_root, path, validators.last_modified, headers))
+        .flatten();
+    if let Some((encoding, _)) = &sidecar {
+        validators = validators.for_encoding(encoding.name());
+    }
+
+    // Other text is compressed on the fly by the CompressionLayer, which changes the bytes:
+    // such responses get a weak tag (which also lets the layer compress them) and no ranges
+    let on_the_fly = sidecar.is_none()
+        && compress::is_text(mime)
+        && len >= compress::MIN_SIZE
+        && !headers.contains_key(header::RANGE)
+        && compress::accepts_compression(headers);
+    if on_the_fly {
+        validators = validators.weak();
+    }
+
+    let vary = AppendHeaders(
+        (compressible || compress::is_text(mime)).then_some((header::VARY, "accept-encoding")),
+    );
+    let accept_ranges = AppendHeaders(
+        (!on_the_fly && rewrite_base.is_none()).then_some((header::ACCEPT_RANGES, "bytes")),
     );
+    let cache_headers = [
+        (header::ETAG, validators.etag.clone()),
+        (header::LAST_MODIFIED, validators.last_modified_header()),
+        (
+            header::CACHE_CONTROL,
+            cache::cache_control(&state.config, ext).to_string(),
+        ),
+    ];
+
+    if validators.not_modified(headers) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers, vary, accept_ranges).into_response());
+    }
 
-    Ok((
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, mime)],
-        data,
-    )
-        .into_response())
+    // Rewritten files are always sent whole, so they don't offer ranges
+    if let Some(base) = rewrite_base {
+        let (path, ext, base) = (path.to_path_buf(), ext.to_string(), base.to_string());
+        let body = tokio::task::spawn_blocking(move || rewrite::rewrite_file(&path, &ext, &base))
+            .await
+            .map_err(|e| AppError::Internal(format!("Rewrite failed: {e}")))??;
+        return Ok((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, mime.to_string()),
+                (header::CONTENT_LENGTH, body.len().to_string()),
+            ],
+            cache_headers,
+            vary,
+            body,
+        )
+            .into_response());
+    }
+
+    if let Some((encoding, sidecar_path)) = sidecar {
+        let file = tokio::fs::File::open(&sidecar_path).await?;
+        let len = file.metadata().await?.len();
+        return Ok((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, mime.to_string()),
+                (header::CONTENT_ENCODING, encoding.name().to_string()),
+                (header::CONTENT_LENGTH, len.to_string()),
+            ],
+            cache_headers,
+            vary,
+            accept_ranges,
+            range::file_body(file, 0, len).await?,
+        )
+            .into_response());
+    }
+
+    let range_request = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| validators.if_range_matches(headers))
+        .map_or(RangeRequest::Full, |v| range::parse(v, len));
+
+    match range_request {
+        RangeRequest::Full => Ok((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, mime.to_string()),
+                (header::CONTENT_LENGTH, len.to_string()),
+            ],
+            cache_headers,
+            vary,
+            accept_ranges,
+            range::file_body(file, 0, len).await?,
+        )
+            .into_response()),
+        RangeRequest::Unsatisfiable => Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+            cache_headers,
+            accept_ranges,
+        )
+            .into_response()),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    (header::CONTENT_TYPE, mime.to_string()),
+                    (header::CONTENT_LENGTH, range.len().to_string()),
+                    (header::CONTENT_RANGE, range.content_range(len)),
+                ],
+                cache_headers,
+                accept_ranges,
+                range::file_body(file, range.start, range.len()).await?,
+            )
+                .into_response())
+        }
+        RangeRequest::Partial(ranges) => {
+            let (boundary, body_len, body) =
+                range::multipart_body(path.to_path_buf(), &ranges, len, mime);
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        format!("multipart/byteranges; boundary={boundary}"),
+                    ),
+                    (header::CONTENT_LENGTH, body_len.to_string()),
+                ],
+                cache_headers,
+                accept_ranges,
+                body,
+            )
+                .into_response())
+        }
+    }
 }
 
-fn mime_from_extension(ext: &str) -> &'static str {
+pub fn mime_from_extension(ext: &str) -> &'static str {
     match ext.to_lowercase().as_str() {
         "html" | "htm" => "text/html; charset=utf-8",
         "css" => "text/css; charset=utf-8",
@@ -247,140 +835,7 @@ fn mime_from_extension(ext: &str) -> &'static str {
     }
 }
 
-fn extract_zip(data: &[u8], dest: &StdPath) -> Result<(), AppError> {
-    let cursor = std::io::Cursor::new(data);
-    let mut archive = zip::ZipArchive::new(cursor)
-        .map_err(|e| AppError::BadRequest(format!("Invalid zip file: {e}")))?;
-
-    for i in 0..archive.len() {
-        let mut file = archive
-            .by_index(i)
-            .map_err(|e| AppError::BadRequest(format!("Failed to read zip entry: {e}")))?;
-
-        let raw_name = file.name().to_string();
-        let entry_path = sanitize_archive_path(&raw_name)?;
-
-        if file.is_dir() {
-            std::fs::create_dir_all(dest.join(&entry_path))?;
-            continue;
-        }
-
-        let out_path = dest.join(&entry_path);
-        if let Some(parent) = out_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-
-        let mut out_file = std::fs::File::create(&out_path)?;
-        std::io::copy(&mut file, &mut out_file)?;
-    }
-
-    // If all files share a common top-level directory, flatten it
-    flatten_single_root(dest)?;
-
-    Ok(())
-}
-
-fn extract_tar_gz(data: &[u8], dest: &StdPath) -> Result<(), AppError> {
-    let cursor = std::io::Cursor::new(data);
-    let gz = flate2::read::GzDecoder::new(cursor);
-    let mut archive = tar::Archive::new(gz);
-
-    for entry in archive
-        .entries()
-        .map_err(|e| AppError::BadRequest(format!("Invalid tar.gz: {e}")))?
-    {
-        let mut entry =
-            entry.map_err(|e| AppError::BadRequest(format!("Failed to read tar entry: {e}")))?;
-
-        let raw_path = entry
-            .path()
-            .map_err(|e| AppError::BadRequest(format!("Invalid path in archive: {e}")))?
-            .to_string_lossy()
-            .to_string();
-
-        let entry_path = sanitize_archive_path(&raw_path)?;
-
-        let out_path = dest.join(&entry_path);
-
-        if entry.header().entry_type().is_dir() {
-            std::fs::create_dir_all(&out_path)?;
-        } else if entry.header().entry_type().is_file() {
-            if let Some(parent) = out_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            let mut out_file = std::fs::File::create(&out_path)?;
-            std::io::copy(&mut entry, &mut out_file)?;
-        }
-        // Skip symlinks and other special entries
-    }
-
-    flatten_single_root(dest)?;
-
-    Ok(())
-}
-
-fn sanitize_archive_path(raw: &str) -> Result<PathBuf, AppError> {
-    let path = StdPath::new(raw);
-
-    // Reject absolute paths
-    if path.has_root() {
-        return Err(AppError::BadRequest(format!(
-            "Absolute path in archive: {raw}"
-        )));
-    }
-
-    // Build sanitized path, rejecting .. and dotfiles
-    let mut result = PathBuf::new();
-    for component in path.components() {
-        match component {
-            std::path::Component::Normal(c) => {
-                let s = c.to_string_lossy();
-                if s.starts_with('.') && s != "." {
-                    return Err(AppError::BadRequest(format!(
-                        "Hidden file in archive: {raw}"
-                    )));
-                }
-                result.push(c);
-            }
-            std::path::Component::ParentDir => {
-                return Err(AppError::BadRequest(format!(
-                    "Path traversal in archive: {raw}"
-                )));
-            }
-            _ => {} // skip CurDir, Prefix, RootDir
-        }
-    }
-
-    if result.as_os_str().is_empty() {
-        result.push(".");
-    }
-
-    Ok(result)
-}
-
-/// If all extracted contents sit inside a single top-level directory, move them up.
-fn flatten_single_root(dir: &StdPath) -> Result<(), AppError> {
-    let entries: Vec<_> = std::fs::read_dir(dir)?
-        .filter_map(|e| e.ok())
-        .collect();
-
-    if entries.len() == 1 && entries[0].file_type().map(|t| t.is_dir()).unwrap_or(false) {
-        let single_dir = entries[0].path();
-        let temp_name = dir.join("__flatten_temp__");
-        std::fs::rename(&single_dir, &temp_name)?;
-
-        // Move all contents from the single dir up to parent
-        for entry in std::fs::read_dir(&temp_name)? {
-            let entry = entry?;
-            std::fs::rename(entry.path(), dir.join(entry.file_name()))?;
-        }
-        std::fs::remove_dir(&temp_name)?;
-    }
-
-    Ok(())
-}
-
-fn list_files_recursive(dir: &StdPath) -> Result<(Vec<FileEntry>, u64), AppError> {
+pub fn list_files_recursive(dir: &StdPath) -> Result<(Vec<FileEntry>, u64), AppError> {
     let mut files = Vec::new();
     let mut total_size = 0u64;
 
@@ -423,17 +878,3 @@ fn list_files_recursive(dir: &StdPath) -> Result<(Vec<FileEntry>, u64), AppError
     files.sort_by(|a, b| a.path.cmp(&b.path));
     Ok((files, total_size))
 }
-
-fn copy_dir_recursive(src: &StdPath, dst: &StdPath) -> Result<(), std::io::Error> {
-    std::fs::create_dir_all(dst)?;
-    for entry in std::fs::read_dir(src)? {
-        let entry = entry?;
-        let target = dst.join(entry.file_name());
-        if entry.file_type()?.is_dir() {
-            copy_dir_recursive(&entry.path(), &target)?;
-        } else {
-            std::fs::copy(entry.path(), target)?;
-        }
-    }
-    Ok(())
-}
@@ -0,0 +1,210 @@
+//! Built-in HTTPS for deployments without a TLS-terminating proxy. Each host name gets its
+//! own certificate from an ACME CA (TLS-ALPN-01 challenge), picked per connection by SNI.
+//! Certificates and the ACME account are cached in `DATA_DIR/certs` and renewed in the
+//! background well before they expire.
+
+use axum::body::Body;
+use axum::http::Request;
+use axum::response::Response;
+use futures_util::StreamExt;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use rustls_acme::acme::ACME_TLS_ALPN_NAME;
+use rustls_acme::caches::DirCache;
+use rustls_acme::{AcmeConfig, ResolvesServerCertAcme};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tower::{Service, ServiceExt};
+
+use crate::config::Config;
+use crate::db::Db;
+use crate::AppState;
+
+/// How often the set of hosts is compared against the configured and verified domains.
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificates for every host the server answers HTTPS for.
+pub struct CertManager {
+    config: Config,
+    client_config: Option<Arc<ClientConfig>>,
+    hosts: Mutex<HashMap<String, HostCert>>,
+}
+
+struct HostCert {
+    resolver: Arc<ResolvesServerCertAcme>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl std::fmt::Debug for CertManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertManager").finish_non_exhaustive()
+    }
+}
+
+impl CertManager {
+    /// Start managing certificates for the configured hosts and verified custom domains.
+    pub fn start(state: &AppState) -> Arc<Self> {
+        let config = state.config.clone();
+        std::fs::create_dir_all(config.certs_dir()).expect("Failed to create certs directory");
+        let client_config = config.acme_ca_file.as_ref().map(|path| {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path).expect("Failed to read ACME_CA_FILE") {
+                roots
+                    .add(cert.expect("ACME_CA_FILE must contain PEM certificates"))
+                    .expect("ACME_CA_FILE contains an invalid certificate");
+            }
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        });
+
+        let manager = Arc::new(Self {
+            config,
+            client_config,
+            hosts: Mutex::new(HashMap::new()),
+        });
+
+        let db = state.db.clone();
+        let background = manager.clone();
+        tokio::spawn(async move {
+            loop {
+                match background.wanted_hosts(&db) {
+                    Ok(hosts) => background.sync(hosts),
+                    Err(e) => tracing::warn!("Failed to list domains for certificates: {e}"),
+                }
+                tokio::time::sleep(SYNC_INTERVAL).await;
+            }
+        });
+        manager
+    }
+
+    fn wanted_hosts(&self, db: &Db) -> Result<BTreeSet<String>, crate::error::AppError> {
+        let mut hosts: BTreeSet<String> = self.config.tls_domains.iter().cloned().collect();
+        hosts.extend(db.verified_domains()?);
+        Ok(hosts)
+    }
+
+    /// Start issuing for new hosts and drop hosts that are no longer wanted, such as
+    /// removed custom domains.
+    fn sync(&self, wanted: BTreeSet<String>) {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts.retain(|host, cert| {
+            let keep = wanted.contains(host);
+            if !keep {
+                cert.task.abort();
+                tracing::info!("Stopped managing the certificate for {host}");
+            }
+            keep
+        });
+        for host in wanted {
+            if let Entry::Vacant(entry) = hosts.entry(host) {
+                let cert = self.manage(entry.key());
+                entry.insert(cert);
+            }
+        }
+    }
+
+    /// Load or order the certificate for one host. The ACME state keeps renewing it until
+    /// the task is aborted.
+    fn manage(&self, host: &str) -> HostCert {
+        let contact = self
+            .config
+            .acme_contact
+            .iter()
+            .map(|c| format!("mailto:{c}"));
+        let mut acme = AcmeConfig::new([host])
+            .contact(contact)
+            .directory(&self.config.acme_directory)
+            .cache(DirCache::new(self.config.certs_dir()));
+        if let Some(client_config) = &self.client_config {
+            acme = acme.client_tls_config(client_config.clone());
+        }
+
+        let mut state = acme.state();
+        let resolver = state.resolver();
+        let host = host.to_string();
+        let task = tokio::spawn(async move {
+            while let Some(event) = state.next().await {
+                match event {
+                    Ok(ok) => tracing::info!("Certificate for {host}: {ok:?}"),
+                    Err(e) => tracing::warn!("Certificate for {host}: {e}"),
+                }
+            }
+        });
+        HostCert { resolver, task }
+    }
+}
+
+impl ResolvesServerCert for CertManager {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello.server_name()?.to_ascii_lowercase();
+        let resolver = self.hosts.lock().unwrap().get(&host)?.resolver.clone();
+        // Answers TLS-ALPN-01 challenges as well as normal handshakes
+        resolver.resolve(client_hello)
+    }
+}
+
+/// Accept HTTPS connections on `addr` and hand them to `app`.
+pub async fn serve<S>(addr: SocketAddr, certs: Arc<CertManager>, app: S)
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(certs);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec(), ACME_TLS_ALPN_NAME.to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .expect("Failed to bind TLS listener");
+    tracing::info!("Listening for HTTPS on {addr}");
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let Ok(Ok(stream)) =
+                tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+            else {
+                return;
+            };
+            // A validation connection from the CA is done once the handshake completes
+            if stream.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN_NAME) {
+                return;
+            }
+
+            let service = TowerToHyperService::new(
+                app.map_request(|request: Request<hyper::body::Incoming>| request.map(Body::new)),
+            );
+            let connection = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades();
+            if let Err(e) = connection.await {
+                tracing::debug!("HTTPS connection error: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,386 @@
+//! Resumable uploads following the tus 1.0.0 protocol (core, creation and termination).
+//! Chunks are appended to a file under `DATA_DIR/uploads`; once the last byte arrives the
+//! file goes through the same extraction and publish pipeline as `site::upload_site`.
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use std::io::SeekFrom;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::auth::AuthUser;
+use crate::db::PendingUpload;
+use crate::error::AppError;
+use crate::site;
+use crate::AppState;
+
+const TUS_VERSION: &str = "1.0.0";
+
+/// Unfinished uploads older than this are discarded.
+const UPLOAD_MAX_AGE_HOURS: u32 = 24;
+
+/// Unfinished uploads one user can have at a time.
+const MAX_PENDING_UPLOADS: usize = 3;
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+
+pub async fn options(State(state): State<AppState>) -> Response {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_RESUMABLE, TUS_VERSION.to_string()),
+            (
+                HeaderName::from_static("tus-version"),
+                TUS_VERSION.to_string(),
+            ),
+            (
+                HeaderName::from_static("tus-extension"),
+                "creation,termination".to_string(),
+            ),
+            (
+                HeaderName::from_static("tus-max-size"),
+                state.config.max_upload_bytes.to_string(),
+            ),
+        ],
+    )
+        .into_response()
+}
+
+/// Start a new upload. The archive's name travels in `Upload-Metadata` as `filename <base64>`.
+pub async fn create_upload(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    expire_stale_uploads(&state);
+
+    let length: u64 = header_str(&headers, &UPLOAD_LENGTH)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| AppError::BadRequest("Missing or invalid Upload-Length".to_string()))?;
+    if length > state.config.max_upload_bytes {
+        return Err(AppError::PayloadTooLarge(format!(
+            "Upload exceeds maximum size of {} MB",
+            state.config.max_upload_bytes / (1024 * 1024)
+        )));
+    }
+    make_room(&state, &auth.username, length)?;
+
+    let filename = header_str(&headers, &UPLOAD_METADATA)
+        .and_then(|v| metadata_value(v, "filename"))
+        .unwrap_or_else(|| "upload".to_string());
+
+    let id = format!("{:032x}", rand::random::<u128>());
+    std::fs::create_dir_all(state.config.uploads_dir())?;
+    std::fs::File::create(state.config.uploads_dir().join(&id))?;
+    state
+        .db
+        .create_upload(&id, &auth.username, &filename, length)?;
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (header::LOCATION, format!("/api/site/tus/{id}")),
+            (TUS_RESUMABLE, TUS_VERSION.to_string()),
+        ],
+    )
+        .into_response())
+}
+
+pub async fn upload_status(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let upload = find_upload(&state, &auth, &id)?;
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, upload.offset.to_string()),
+            (UPLOAD_LENGTH, upload.length.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (TUS_RESUMABLE, TUS_VERSION.to_string()),
+        ],
+    )
+        .into_response())
+}
+
+/// Append a chunk at `Upload-Offset`. The request that completes the upload also deploys it.
+pub async fn append_chunk(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    if header_str(&headers, &header::CONTENT_TYPE) != Some("application/offset+octet-stream") {
+        return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+    }
+
+    let _guard = state.deploy_locks.acquire_upload(&id)?;
+    let upload = find_upload(&state, &auth, &id)?;
+
+    let offset: u64 = header_str(&headers, &UPLOAD_OFFSET)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| AppError::BadRequest("Missing or invalid Upload-Offset".to_string()))?;
+    if offset != upload.offset {
+        return Err(AppError::Conflict(format!(
+            "Upload-Offset {offset} does not match current offset {}",
+            upload.offset
+        )));
+    }
+
+    // Drop anything past the recorded offset, left over from an interrupted request
+    let path = state.config.uploads_dir().join(&upload.id);
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .await?;
+    file.set_len(upload.offset).await?;
+    file.seek(SeekFrom::Start(upload.offset)).await?;
+
+    // Keep whatever arrived before a dropped connection, so the client can resume from there
+    let mut written = 0u64;
+    let mut stream = body.into_data_stream();
+    let result = async {
+        while let Some(chunk) = futures_util::StreamExt::next(&mut stream).await {
+            let chunk =
+                chunk.map_err(|e| AppError::BadRequest(format!("Failed to read upload: {e}")))?;
+            if upload.offset + written + chunk.len() as u64 > upload.length {
+                return Err(AppError::BadRequest(
+                    "Chunk goes past Upload-Length".to_string(),
+                ));
+            }
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        Ok(())
+    }
+    .await;
+
+    file.flush().await?;
+    drop(file);
+    let new_offset = upload.offset + written;
+    state.db.set_upload_offset(&upload.id, new_offset)?;
+    result?;
+
+    if new_offset == upload.length {
+        // A busy deploy lock or a server-side failure can pass, so keep the upload and let the
+        // client retry with an empty PATCH at the final offset. Anything else is wrong with
+        // the upload itself and retrying can't help.
+        let finished = {
+            let (state, upload) = (state.clone(), upload.clone());
+            tokio::task::spawn_blocking(move || finish_upload(&state, &upload))
+                .await
+                .map_err(|e| AppError::Internal(format!("Upload failed: {e}")))
+                .and_then(|finished| finished)
+        };
+        if !matches!(finished, Err(AppError::Conflict(_) | AppError::Internal(_))) {
+            let _ = std::fs::remove_file(&path);
+            state.db.delete_upload(&upload.id)?;
+        }
+        finished?;
+    }
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [
+            (UPLOAD_OFFSET, new_offset.to_string()),
+            (TUS_RESUMABLE, TUS_VERSION.to_string()),
+        ],
+    )
+        .into_response())
+}
+
+pub async fn delete_upload(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    let _guard = state.deploy_locks.acquire_upload(&id)?;
+    let upload = find_upload(&state, &auth, &id)?;
+
+    let _ = std::fs::remove_file(state.config.uploads_dir().join(&upload.id));
+    state.db.delete_upload(&upload.id)?;
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(TUS_RESUMABLE, TUS_VERSION.to_string())],
+    )
+        .into_response())
+}
+
+/// Extract and publish a complete upload. Blocking, so it runs off the async workers.
+fn finish_upload(state: &AppState, upload: &PendingUpload) -> Result<(), AppError> {
+    let path = state.config.uploads_dir().join(&upload.id);
+    let staged = site::stage_upload(state, &[(upload.filename.clone(), &path)], upload.length)?;
+    site::publish_staged(state, &upload.username, staged)?;
+    Ok(())
+}
+
+/// Make room for a new upload of `length` bytes. A user's unfinished uploads are capped in
+/// number and their declared lengths count against the disk quota, so abandoned uploads
+/// can't fill the disk. The oldest idle uploads are discarded first.
+fn make_room(state: &AppState, username: &str, length: u64) -> Result<(), AppError> {
+    let quota = state.config.disk_quota_bytes;
+    let too_large = || {
+        AppError::PayloadTooLarge(format!(
+            "Upload would exceed disk quota of {} MB",
+            quota / (1024 * 1024)
+        ))
+    };
+    if length > quota {
+        return Err(too_large());
+    }
+
+    let pending = state.db.pending_uploads(username)?;
+    let mut count = pending.len();
+    let mut total: u64 = pending.iter().map(|u| u.length).sum();
+    for upload in pending {
+        if count < MAX_PENDING_UPLOADS && total + length <= quota {
+            break;
+        }
+        // One that is receiving data right now stays
+        let Ok(_guard) = state.deploy_locks.acquire_upload(&upload.id) else {
+            continue;
+        };
+        let _ = std::fs::remove_file(state.config.uploads_dir().join(&upload.id));
+        state.db.delete_upload(&upload.id)?;
+        count -= 1;
+        total -= upload.length;
+    }
+
+    if count >= MAX_PENDING_UPLOADS {
+        return Err(AppError::Conflict(
+            "Too many uploads in progress, please wait for one to finish".to_string(),
+        ));
+    }
+    if total + length > quota {
+        return Err(too_large());
+    }
+    Ok(())
+}
+
+fn find_upload(state: &AppState, auth: &AuthUser, id: &str) -> Result<PendingUpload, AppError> {
+    state
+        .db
+        .get_upload(id)?
+        .filter(|u| u.username == auth.username)
+        .ok_or_else(|| AppError::NotFound("Upload not found".to_string()))
+}
+
+fn expire_stale_uploads(state: &AppState) {
+    match state.db.expire_uploads(UPLOAD_MAX_AGE_HOURS) {
+        Ok(ids) => {
+            for id in ids {
+                let _ = std::fs::remove_file(state.config.uploads_dir().join(id));
+            }
+        }
+        Err(e) => tracing::warn!("Failed to expire uploads: {e}"),
+    }
+}
+
+/// Requests for a protocol version we don't speak get 412 with the supported version.
+fn version_mismatch(headers: &HeaderMap) -> Option<Response> {
+    if header_str(headers, &TUS_RESUMABLE) == Some(TUS_VERSION) {
+        return None;
+    }
+    Some(
+        (
+            StatusCode::PRECONDITION_FAILED,
+            [(HeaderName::from_static("tus-version"), TUS_VERSION)],
+        )
+            .into_response(),
+    )
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v: &HeaderValue| v.to_str().ok())
+}
+
+/// Look up one key in an `Upload-Metadata` header: comma-separated `key base64value` pairs.
+fn metadata_value(header: &str, key: &str) -> Option<String> {
+    header.split(',').find_map(|pair| {
+        let mut parts = pair.trim().splitn(2, ' ');
+        if parts.next()? != key {
+            return None;
+        }
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(parts.next()?.trim())
+            .ok()?;
+        String::from_utf8(decoded).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_value_decodes_the_requested_key() {
+        // "site.zip" and "application/zip"
+        let header = "filename c2l0ZS56aXA=,filetype YXBwbGljYXRpb24vemlw";
+        assert_eq!(
+            metadata_value(header, "filename").as_deref(),
+            Some("site.zip")
+        );
+        assert_eq!(
+            metadata_value(header, "filetype").as_deref(),
+            Some("application/zip")
+        );
+    }
+
+    #[test]
+    fn metadata_value_tolerates_spaces_around_pairs() {
+        let header = "  filetype YXBwbGljYXRpb24vemlw ,  filename c2l0ZS56aXA=  ";
+        assert_eq!(
+            metadata_value(header, "filename").as_deref(),
+            Some("site.zip")
+        );
+    }
+
+    #[test]
+    fn metadata_value_decodes_utf8_names() {
+        // "เว็บ.zip"
+        let header = "filename 4LmA4Lin4LmH4LiaLnppcA==";
+        assert_eq!(
+            metadata_value(header, "filename").as_deref(),
+            Some("เว็บ.zip")
+        );
+    }
+
+    #[test]
+    fn metadata_value_rejects_missing_and_malformed_values() {
+        assert_eq!(metadata_value("", "filename"), None);
+        assert_eq!(metadata_value("filetype YQ==", "filename"), None);
+        // A key without a value, as tus allows, has nothing to decode
+        assert_eq!(metadata_value("filename", "filename"), None);
+        assert_eq!(metadata_value("filename not*base64", "filename"), None);
+        // Valid base64 of bytes that aren't UTF-8
+        assert_eq!(metadata_value("filename //8=", "filename"), None);
+    }
+
+    #[test]
+    fn metadata_value_matches_whole_keys_only() {
+        assert_eq!(metadata_value("filenames c2l0ZS56aXA=", "filename"), None);
+        assert_eq!(metadata_value("name c2l0ZS56aXA=", "filename"), None);
+    }
+}
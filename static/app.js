@@ -11,9 +11,19 @@ document.addEventListener('DOMContentLoaded', () => {
 
     // Load initial site info
     loadSiteInfo();
+    loadDeployments();
+    loadSettings();
+    loadDomains();
+
+    const folderInput = document.getElementById('folder-input');
+    const folderLink = document.getElementById('folder-link');
 
     // Click to browse
     uploadZone.addEventListener('click', () => fileInput.click());
+    folderLink.addEventListener('click', (e) => {
+        e.stopPropagation();
+        folderInput.click();
+    });
 
     // Drag & drop
     uploadZone.addEventListener('dragover', (e) => {
@@ -25,21 +35,69 @@ document.addEventListener('DOMContentLoaded', () => {
         uploadZone.classList.remove('dragover');
     });
 
-    uploadZone.addEventListener('drop', (e) => {
+    uploadZone.addEventListener('drop', async (e) => {
         e.preventDefault();
         uploadZone.classList.remove('dragover');
-        if (e.dataTransfer.files.length > 0) {
-            uploadFile(e.dataTransfer.files[0]);
+        const entries = await collectDropped(e.dataTransfer);
+        if (entries.length > 0) {
+            uploadFiles(entries);
         }
     });
 
     // File input change
     fileInput.addEventListener('change', () => {
         if (fileInput.files.length > 0) {
-            uploadFile(fileInput.files[0]);
+            uploadFiles([...fileInput.files].map(file => ({ file, path: file.name })));
+            fileInput.value = '';
+        }
+    });
+
+    folderInput.addEventListener('change', () => {
+        const entries = [...folderInput.files]
+            .map(file => ({ file, path: file.webkitRelativePath || file.name }))
+            .filter(entry => !isHidden(entry.path));
+        if (entries.length > 0) {
+            uploadFiles(entries);
         }
+        folderInput.value = '';
     });
 
+    // Dotfiles like .DS_Store or .git are rejected by the server, so leave them out
+    function isHidden(path) {
+        return path.split('/').some(part => part.startsWith('.'));
+    }
+
+    // Walk dropped folders, keeping each file's path relative to the drop
+    async function collectDropped(dataTransfer) {
+        const roots = [...dataTransfer.items]
+            .map(item => item.webkitGetAsEntry && item.webkitGetAsEntry())
+            .filter(Boolean);
+        if (roots.length === 0) {
+            return [...dataTransfer.files].map(file => ({ file, path: file.name }));
+        }
+
+        const entries = [];
+        const walk = async (entry, prefix) => {
+            if (entry.isFile) {
+                const file = await new Promise((resolve, reject) => entry.file(resolve, reject));
+                entries.push({ file, path: prefix + entry.name });
+            } else if (entry.isDirectory) {
+                const reader = entry.createReader();
+                let batch;
+                do {
+                    batch = await new Promise((resolve, reject) => reader.readEntries(resolve, reject));
+                    for (const child of batch) {
+                        await walk(child, `${prefix}${entry.name}/`);
+                    }
+                } while (batch.length > 0);
+            }
+        };
+        for (const root of roots) {
+            await walk(root, '');
+        }
+        return entries.filter(entry => !isHidden(entry.path));
+    }
+
     // Delete button
     if (deleteBtn) {
         deleteBtn.addEventListener('click', async () => {
@@ -50,6 +108,7 @@ document.addEventListener('DOMContentLoaded', () => {
                 const data = await res.json();
                 if (data.success) {
                     loadSiteInfo();
+                    loadDeployments();
                     uploadResult.innerHTML = '<div class="notification is-warning is-light">All files deleted.</div>';
                     uploadResult.style.display = '';
                 }
@@ -61,45 +120,49 @@ document.addEventListener('DOMContentLoaded', () => {
         });
     }
 
-    async function uploadFile(file) {
-        const validTypes = ['.zip', '.tar.gz', '.tgz'];
-        const isValid = validTypes.some(ext => file.name.toLowerCase().endsWith(ext));
-        if (!isValid) {
-            alert('Please upload a .zip or .tar.gz file');
-            return;
-        }
-
+    // Every upload is checked with a dry run first, so the user sees what would be
+    // added, changed and removed before their live site is replaced.
+    async function uploadFiles(entries) {
         uploadProgress.style.display = '';
         uploadResult.style.display = 'none';
-        uploadStatus.textContent = `Uploading ${file.name}...`;
+        uploadStatus.textContent = entries.length === 1
+            ? `Checking ${entries[0].path}...`
+            : `Checking ${entries.length} files...`;
         progressBar.removeAttribute('value');
 
-        const formData = new FormData();
-        formData.append('file', file);
-
         try {
-            const xhr = new XMLHttpRequest();
-            xhr.open('POST', '/api/site/upload');
+            const result = await uploadMultipart(entries, true);
+            uploadProgress.style.display = 'none';
 
-            xhr.upload.addEventListener('progress', (e) => {
-                if (e.lengthComputable) {
-                    const pct = Math.round((e.loaded / e.total) * 100);
-                    progressBar.value = pct;
-                    uploadStatus.textContent = `Uploading... ${pct}%`;
-                }
-            });
+            if (result.status !== 200 || !result.data.success) {
+                showUploadError(result.data.error || 'Upload failed');
+                return;
+            }
+            renderDiff(result.data);
 
-            const result = await new Promise((resolve, reject) => {
-                xhr.onload = () => {
-                    try {
-                        resolve({ status: xhr.status, data: JSON.parse(xhr.responseText) });
-                    } catch {
-                        reject(new Error('Invalid response'));
-                    }
-                };
-                xhr.onerror = () => reject(new Error('Network error'));
-                xhr.send(formData);
+            document.getElementById('confirm-publish-btn').addEventListener('click', () => publishFiles(entries));
+            document.getElementById('cancel-publish-btn').addEventListener('click', () => {
+                uploadResult.style.display = 'none';
+                uploadResult.innerHTML = '';
             });
+        } catch (e) {
+            uploadProgress.style.display = 'none';
+            showUploadError(`Upload failed: ${e.message}`);
+        }
+    }
+
+    async function publishFiles(entries) {
+        uploadProgress.style.display = '';
+        uploadResult.style.display = 'none';
+        uploadStatus.textContent = entries.length === 1
+            ? `Uploading ${entries[0].path}...`
+            : `Uploading ${entries.length} files...`;
+        progressBar.removeAttribute('value');
+
+        try {
+            const result = entries.length === 1 && entries[0].file.size > RESUMABLE_THRESHOLD
+                ? await uploadResumable(entries[0])
+                : await uploadMultipart(entries, false);
 
             uploadProgress.style.display = 'none';
 
@@ -108,18 +171,684 @@ document.addEventListener('DOMContentLoaded', () => {
                     <div class="notification is-success is-light">
                         Upload successful! <a href="${result.data.site_url}" target="_blank">View your site</a>
                     </div>`;
+                uploadResult.style.display = '';
             } else {
-                uploadResult.innerHTML = `
-                    <div class="notification is-danger is-light">
-                        ${result.data.error || 'Upload failed'}
-                    </div>`;
+                showUploadError(result.data.error || 'Upload failed');
             }
-            uploadResult.style.display = '';
             loadSiteInfo();
+            loadDeployments();
         } catch (e) {
             uploadProgress.style.display = 'none';
-            uploadResult.innerHTML = `<div class="notification is-danger is-light">Upload failed: ${e.message}</div>`;
+            showUploadError(`Upload failed: ${e.message}`);
+        }
+    }
+
+    function showUploadError(message) {
+        uploadResult.innerHTML = `<div class="notification is-danger is-light">${escapeHtml(message)}</div>`;
+        uploadResult.style.display = '';
+    }
+
+    const DIFF_LIST_LIMIT = 50;
+
+    function renderDiff(data) {
+        const { added, removed, changed, unchanged_count } = data.diff;
+        const section = (title, files, tag) => {
+            if (files.length === 0) return '';
+            const items = files.slice(0, DIFF_LIST_LIMIT)
+                .map(f => `<li><span class="tag ${tag} is-light mr-2">${title}</span>${escapeHtml(f.path)} <span class="has-text-grey">(${formatSize(f.size)})</span></li>`)
+                .join('');
+            const more = files.length > DIFF_LIST_LIMIT
+                ? `<li class="has-text-grey">...and ${files.length - DIFF_LIST_LIMIT} more</li>`
+                : '';
+            return `<ul class="diff-list">${items}${more}</ul>`;
+        };
+
+        const warning = removed.length > 0
+            ? `<p class="has-text-danger mb-2">${removed.length} file(s) on your live site are not in this upload and will be removed.</p>`
+            : '';
+
+        uploadResult.innerHTML = `
+            <div class="notification is-info is-light">
+                <p class="mb-2"><strong>Review before publishing:</strong>
+                    ${added.length} added, ${changed.length} changed, ${removed.length} removed, ${unchanged_count} unchanged.
+                    Disk usage will go from ${formatSize(data.current_disk_usage_bytes)} to ${formatSize(data.disk_usage_bytes)}.</p>
+                ${warning}
+                <div class="file-list mb-3">
+                    ${section('added', added, 'is-success')}
+                    ${section('changed', changed, 'is-warning')}
+                    ${section('removed', removed, 'is-danger')}
+                </div>
+                <div class="buttons">
+                    <button id="confirm-publish-btn" class="button is-link is-small">Publish</button>
+                    <button id="cancel-publish-btn" class="button is-small">Cancel</button>
+                </div>
+            </div>`;
+        uploadResult.style.display = '';
+    }
+
+    function uploadMultipart(entries, dryRun) {
+        // Each part's filename carries its path relative to the site root
+        const formData = new FormData();
+        for (const { file, path } of entries) {
+            formData.append('file', file, path);
+        }
+
+        const xhr = new XMLHttpRequest();
+        xhr.open('POST', dryRun ? '/api/site/upload?dry_run=true' : '/api/site/upload');
+
+        xhr.upload.addEventListener('progress', (e) => {
+            if (e.lengthComputable) {
+                showProgress(e.loaded, e.total);
+            }
+        });
+
+        return new Promise((resolve, reject) => {
+            xhr.onload = () => {
+                try {
+                    resolve({ status: xhr.status, data: JSON.parse(xhr.responseText) });
+                } catch {
+                    reject(new Error('Invalid response'));
+                }
+            };
+            xhr.onerror = () => reject(new Error('Network error'));
+            xhr.send(formData);
+        });
+    }
+
+    // Large archives go through the tus endpoint in chunks, so a dropped
+    // connection resumes where it stopped instead of starting over.
+    const RESUMABLE_THRESHOLD = 5 * 1024 * 1024;
+    const RESUMABLE_CHUNK = 5 * 1024 * 1024;
+    const TUS_HEADERS = { 'Tus-Resumable': '1.0.0' };
+
+    async function uploadResumable({ file, path }) {
+        const key = `tus:${path}:${file.size}:${file.lastModified}`;
+        let url = localStorage.getItem(key);
+        let offset = url ? await serverOffset(url) : null;
+
+        if (offset === null) {
+            const res = await fetch('/api/site/tus', {
+                method: 'POST',
+                headers: {
+                    ...TUS_HEADERS,
+                    'Upload-Length': String(file.size),
+                    'Upload-Metadata': `filename ${btoa(unescape(encodeURIComponent(path)))}`,
+                },
+            });
+            if (res.status !== 201) {
+                return { status: res.status, data: await res.json().catch(() => ({})) };
+            }
+            url = res.headers.get('Location');
+            offset = 0;
+            localStorage.setItem(key, url);
+        }
+
+        // A complete upload that couldn't be deployed yet (say another deploy was running)
+        // is retried by sending an empty chunk at the final offset
+        let failures = 0;
+        let deployed = false;
+        while (!deployed) {
+            showProgress(offset, file.size);
+            let res;
+            try {
+                res = await fetch(url, {
+                    method: 'PATCH',
+                    headers: {
+                        ...TUS_HEADERS,
+                        'Upload-Offset': String(offset),
+                        'Content-Type': 'application/offset+octet-stream',
+                    },
+                    body: file.slice(offset, offset + RESUMABLE_CHUNK),
+                });
+            } catch (e) {
+                if (++failures > 5) throw e;
+                uploadStatus.textContent = 'Connection lost, retrying...';
+                await new Promise(resolve => setTimeout(resolve, 2000 * failures));
+                offset = (await serverOffset(url)) ?? offset;
+                continue;
+            }
+
+            if (res.status !== 204) {
+                // The server keeps uploads whose deploy can be retried
+                if (res.status !== 409 && res.status < 500) localStorage.removeItem(key);
+                return { status: res.status, data: await res.json().catch(() => ({})) };
+            }
+            failures = 0;
+            offset = parseInt(res.headers.get('Upload-Offset'), 10);
+            deployed = offset >= file.size;
+        }
+
+        localStorage.removeItem(key);
+        const info = await fetch('/api/site').then(res => res.json());
+        return { status: 200, data: { success: true, site_url: info.site_url } };
+    }
+
+    async function serverOffset(url) {
+        try {
+            const res = await fetch(url, { method: 'HEAD', headers: TUS_HEADERS });
+            return res.ok ? parseInt(res.headers.get('Upload-Offset'), 10) : null;
+        } catch {
+            return null;
+        }
+    }
+
+    function showProgress(loaded, total) {
+        const pct = Math.round((loaded / total) * 100);
+        progressBar.value = pct;
+        uploadStatus.textContent = `Uploading... ${pct}%`;
+    }
+
+    // --- File manager and editor ---
+
+    const editorBox = document.getElementById('editor-box');
+    const editor = document.getElementById('editor');
+    const editorPath = document.getElementById('editor-path');
+    const editorStatus = document.getElementById('editor-status');
+    const editorBinary = document.getElementById('editor-binary');
+    const saveBtn = document.getElementById('save-btn');
+    const editableTypes = ['html', 'htm', 'css', 'js', 'mjs', 'json', 'md', 'txt', 'svg', 'xml'];
+    let openPath = null;
+    let dirty = false;
+
+    document.getElementById('new-file-btn').addEventListener('click', async () => {
+        const path = prompt('New file path (e.g. about.html or css/theme.css):');
+        if (!path) return;
+        const res = await fetch(fileUrl(path), { method: 'PUT', body: '' });
+        const data = await res.json();
+        if (!data.success) {
+            alert(data.error || 'Failed to create file');
+            return;
+        }
+        await loadSiteInfo();
+        loadDeployments();
+        openFile(data.path);
+    });
+
+    saveBtn.addEventListener('click', saveFile);
+
+    document.getElementById('rename-btn').addEventListener('click', async () => {
+        const to = prompt('Move or rename to:', openPath);
+        if (!to || to === openPath) return;
+        const res = await fetch('/api/site/move', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ from: openPath, to }),
+        });
+        const data = await res.json();
+        if (!data.success) {
+            alert(data.error || 'Failed to rename file');
+            return;
+        }
+        openPath = data.path;
+        editorPath.textContent = openPath;
+        loadSiteInfo();
+        loadDeployments();
+    });
+
+    document.getElementById('delete-file-btn').addEventListener('click', async () => {
+        if (!confirm(`Delete ${openPath}?`)) return;
+        const res = await fetch(fileUrl(openPath), { method: 'DELETE' });
+        const data = await res.json();
+        if (!data.success) {
+            alert(data.error || 'Failed to delete file');
+            return;
+        }
+        closeEditor();
+        loadSiteInfo();
+        loadDeployments();
+    });
+
+    document.getElementById('close-editor-btn').addEventListener('click', () => {
+        if (dirty && !confirm('Discard unsaved changes?')) return;
+        closeEditor();
+    });
+
+    editor.addEventListener('input', () => {
+        dirty = true;
+        editorStatus.textContent = 'Unsaved changes';
+    });
+
+    editor.addEventListener('keydown', (e) => {
+        if (e.key === 'Tab') {
+            e.preventDefault();
+            editor.setRangeText('    ', editor.selectionStart, editor.selectionEnd, 'end');
+            editor.dispatchEvent(new Event('input'));
+        } else if (e.key === 's' && (e.ctrlKey || e.metaKey)) {
+            e.preventDefault();
+            saveFile();
+        }
+    });
+
+    window.addEventListener('beforeunload', (e) => {
+        if (dirty) e.preventDefault();
+    });
+
+    function fileUrl(path) {
+        return '/api/site/files/' + path.split('/').map(encodeURIComponent).join('/');
+    }
+
+    function escapeHtml(text) {
+        return text.replace(/[&<>"']/g, (c) => `&#${c.charCodeAt(0)};`);
+    }
+
+    function formatSize(size) {
+        return size < 1024 ? `${size} B`
+            : size < 1048576 ? `${(size / 1024).toFixed(1)} KB`
+            : `${(size / 1048576).toFixed(1)} MB`;
+    }
+
+    function renderFileTree(files) {
+        const fileList = document.getElementById('file-list');
+        if (files.length === 0) {
+            fileList.innerHTML = '<p class="has-text-grey">No files uploaded yet.</p>';
+            return;
+        }
+
+        // Build a nested folder structure from the flat path list
+        const root = { dirs: {}, files: [] };
+        for (const f of files) {
+            const parts = f.path.split('/');
+            let node = root;
+            for (const dir of parts.slice(0, -1)) {
+                node = node.dirs[dir] ??= { dirs: {}, files: [] };
+            }
+            node.files.push({ name: parts[parts.length - 1], path: f.path, size: f.size });
+        }
+
+        const render = (node) => {
+            let html = '<ul>';
+            for (const [name, child] of Object.entries(node.dirs).sort(([a], [b]) => a.localeCompare(b))) {
+                html += `<li><details open><summary>${escapeHtml(name)}/</summary>${render(child)}</details></li>`;
+            }
+            for (const f of node.files) {
+                const active = f.path === openPath ? ' class="is-active"' : '';
+                html += `<li><a href="#" data-path="${escapeHtml(f.path)}"${active}>${escapeHtml(f.name)}</a>
+                    <span class="is-size-7 has-text-grey">${formatSize(f.size)}</span></li>`;
+            }
+            return html + '</ul>';
+        };
+
+        fileList.innerHTML = `<div class="file-tree">${render(root)}</div>`;
+        for (const link of fileList.querySelectorAll('a[data-path]')) {
+            link.addEventListener('click', (e) => {
+                e.preventDefault();
+                if (dirty && !confirm('Discard unsaved changes?')) return;
+                openFile(link.dataset.path);
+            });
+        }
+    }
+
+    async function openFile(path) {
+        const ext = path.includes('.') ? path.split('.').pop().toLowerCase() : '';
+        const editable = editableTypes.includes(ext);
+
+        openPath = path;
+        dirty = false;
+        editorPath.textContent = path;
+        editorStatus.textContent = '';
+        editorBox.style.display = '';
+        editor.style.display = editable ? '' : 'none';
+        editorBinary.style.display = editable ? 'none' : '';
+        saveBtn.style.display = editable ? '' : 'none';
+        document.getElementById('editor-view').href = fileUrl(path);
+        highlightOpenFile();
+
+        if (!editable) return;
+        editor.value = '';
+        editor.disabled = true;
+        editorStatus.textContent = 'Loading...';
+        try {
+            const res = await fetch(fileUrl(path));
+            if (!res.ok) throw new Error(`HTTP ${res.status}`);
+            editor.value = await res.text();
+            editorStatus.textContent = '';
+        } catch (e) {
+            editorStatus.textContent = `Failed to load file: ${e.message}`;
+        } finally {
+            editor.disabled = false;
+        }
+    }
+
+    async function saveFile() {
+        if (!openPath || editor.style.display === 'none') return;
+        saveBtn.classList.add('is-loading');
+        try {
+            const res = await fetch(fileUrl(openPath), { method: 'PUT', body: editor.value });
+            const data = await res.json();
+            if (data.success) {
+                dirty = false;
+                editorStatus.textContent = `Saved at ${new Date().toLocaleTimeString()}`;
+                loadSiteInfo();
+                loadDeployments();
+            } else {
+                editorStatus.textContent = data.error || 'Save failed';
+            }
+        } catch (e) {
+            editorStatus.textContent = `Save failed: ${e.message}`;
+        } finally {
+            saveBtn.classList.remove('is-loading');
+        }
+    }
+
+    function closeEditor() {
+        openPath = null;
+        dirty = false;
+        editorBox.style.display = 'none';
+        highlightOpenFile();
+    }
+
+    function highlightOpenFile() {
+        for (const link of document.querySelectorAll('.file-tree a[data-path]')) {
+            link.classList.toggle('is-active', link.dataset.path === openPath);
+        }
+    }
+
+    async function loadDeployments() {
+        const deploymentList = document.getElementById('deployment-list');
+        if (!deploymentList) return;
+        try {
+            const res = await fetch('/api/site/deployments');
+            if (!res.ok) return;
+            const data = await res.json();
+
+            if (data.deployments.length === 0) {
+                deploymentList.innerHTML = '<p class="has-text-grey">No deployments yet.</p>';
+                return;
+            }
+
+            let html = `<p class="help mb-2">The last ${data.retention} uploads and the edits made to them are kept for rollback, as long as they fit in one more disk quota.</p>`;
+            html += '<table class="table is-fullwidth is-narrow"><tbody>';
+            for (const d of data.deployments) {
+                const sizeMB = (d.size_bytes / (1024 * 1024)).toFixed(1);
+                const action = d.is_active
+                    ? '<span class="tag is-success is-light">Live</span>'
+                    : `<button class="button is-small is-link is-outlined rollback-btn" data-id="${d.id}">Roll back</button>`;
+                html += `<tr>
+                    <td><strong>#${d.number}</strong>${d.kind === 'edit' ? ' <span class="tag is-light">Edit</span>' : ''}<br><span class="is-size-7 has-text-grey">${d.created_at} UTC</span></td>
+                    <td class="is-size-7">${d.file_count} files<br>${sizeMB} MB</td>
+                    <td class="has-text-right">${action}</td>
+                </tr>`;
+            }
+            html += '</tbody></table>';
+            deploymentList.innerHTML = html;
+
+            for (const btn of deploymentList.querySelectorAll('.rollback-btn')) {
+                btn.addEventListener('click', () => rollback(btn));
+            }
+        } catch (e) {
+            console.error('Failed to load deployments:', e);
+        }
+    }
+
+    async function rollback(btn) {
+        if (!confirm('Make this deployment live again?')) return;
+        btn.classList.add('is-loading');
+        try {
+            const res = await fetch(`/api/site/deployments/${btn.dataset.id}/rollback`, { method: 'POST' });
+            const data = await res.json();
+            if (data.success) {
+                uploadResult.innerHTML = `<div class="notification is-info is-light">Rolled back to deployment #${data.deployment.number}.</div>`;
+            } else {
+                uploadResult.innerHTML = `<div class="notification is-danger is-light">${data.error || 'Rollback failed'}</div>`;
+            }
             uploadResult.style.display = '';
+            loadSiteInfo();
+            loadDeployments();
+        } catch (e) {
+            alert('Failed to roll back');
+            btn.classList.remove('is-loading');
+        }
+    }
+
+    // --- Site settings ---
+    const settingInputs = document.querySelectorAll('.site-setting');
+    const settingsStatus = document.getElementById('settings-status');
+
+    for (const input of settingInputs) {
+        input.addEventListener('change', () => saveSetting(input));
+    }
+
+    async function loadSettings() {
+        try {
+            const res = await fetch('/api/site/settings');
+            if (!res.ok) return;
+            const settings = await res.json();
+            for (const input of settingInputs) {
+                input.checked = Boolean(settings[input.dataset.setting]);
+                input.disabled = false;
+            }
+            showPasswordState(settings.password_protected);
+            siteVisibility.value = settings.visibility;
+            siteVisibility.disabled = false;
+        } catch (e) {
+            console.error('Failed to load settings:', e);
+        }
+    }
+
+    async function saveSetting(input) {
+        input.disabled = true;
+        settingsStatus.textContent = 'Saving...';
+        try {
+            const res = await fetch('/api/site/settings', {
+                method: 'PUT',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ [input.dataset.setting]: input.checked }),
+            });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Save failed');
+            input.checked = Boolean(data[input.dataset.setting]);
+            settingsStatus.textContent = 'Saved';
+        } catch (e) {
+            input.checked = !input.checked;
+            settingsStatus.textContent = `Failed to save: ${e.message}`;
+        } finally {
+            input.disabled = false;
+        }
+    }
+
+    const siteVisibility = document.getElementById('site-visibility');
+
+    siteVisibility.addEventListener('change', saveVisibility);
+
+    async function saveVisibility() {
+        siteVisibility.disabled = true;
+        settingsStatus.textContent = 'Saving...';
+        try {
+            const res = await fetch('/api/site/settings', {
+                method: 'PUT',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ visibility: siteVisibility.value }),
+            });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Save failed');
+            siteVisibility.value = data.visibility;
+            settingsStatus.textContent = 'Saved';
+        } catch (e) {
+            settingsStatus.textContent = `Failed to save: ${e.message}`;
+            loadSettings();
+        } finally {
+            siteVisibility.disabled = false;
+        }
+    }
+
+    const sitePassword = document.getElementById('site-password');
+    const passwordState = document.getElementById('password-state');
+    const clearPasswordBtn = document.getElementById('clear-password-btn');
+
+    document.getElementById('set-password-btn').addEventListener('click', setPassword);
+    clearPasswordBtn.addEventListener('click', clearPassword);
+    sitePassword.addEventListener('keydown', (e) => {
+        if (e.key === 'Enter') setPassword();
+    });
+
+    function showPasswordState(enabled) {
+        passwordState.textContent = enabled
+            ? 'Your site is password protected. Set a new password to change it.'
+            : 'Visitors must enter the password before they can see any page.';
+        clearPasswordBtn.disabled = !enabled;
+    }
+
+    async function setPassword() {
+        const password = sitePassword.value;
+        if (!password) return;
+        await savePassword('PUT', JSON.stringify({ password }));
+        sitePassword.value = '';
+    }
+
+    async function clearPassword() {
+        if (!confirm('Remove the password? Anyone will be able to see your site.')) return;
+        await savePassword('DELETE');
+    }
+
+    async function savePassword(method, body) {
+        settingsStatus.textContent = 'Saving...';
+        try {
+            const res = await fetch('/api/site/password', {
+                method,
+                headers: { 'Content-Type': 'application/json' },
+                body,
+            });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Save failed');
+            showPasswordState(data.password_protected);
+            settingsStatus.textContent = 'Saved';
+        } catch (e) {
+            settingsStatus.textContent = `Failed to save: ${e.message}`;
+        }
+    }
+
+    // --- Share links ---
+    const shareUrl = document.getElementById('share-url');
+    const shareStatus = document.getElementById('share-status');
+
+    document.getElementById('create-share-btn').addEventListener('click', createShareLink);
+    document.getElementById('revoke-share-btn').addEventListener('click', revokeShareLinks);
+    shareUrl.addEventListener('focus', () => shareUrl.select());
+
+    async function createShareLink() {
+        shareStatus.textContent = '';
+        try {
+            const res = await fetch('/api/site/share', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ hours: Number(document.getElementById('share-hours').value) }),
+            });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Failed to create link');
+            shareUrl.value = new URL(data.url, window.location.origin).href;
+            shareUrl.style.display = '';
+            shareUrl.focus();
+            shareStatus.textContent = `Expires ${new Date(data.expires_at).toLocaleString()}`;
+        } catch (e) {
+            shareStatus.textContent = e.message;
+        }
+    }
+
+    async function revokeShareLinks() {
+        if (!confirm('Revoke all share links? Everyone using one loses access.')) return;
+        try {
+            const res = await fetch('/api/site/share', { method: 'DELETE' });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Failed to revoke links');
+            shareUrl.style.display = 'none';
+            shareStatus.textContent = 'All share links revoked';
+        } catch (e) {
+            shareStatus.textContent = e.message;
+        }
+    }
+
+    // --- Custom domains ---
+    const domainList = document.getElementById('domain-list');
+    const domainInput = document.getElementById('domain-input');
+    const domainStatus = document.getElementById('domain-status');
+
+    document.getElementById('add-domain-btn').addEventListener('click', addDomain);
+    domainInput.addEventListener('keydown', (e) => {
+        if (e.key === 'Enter') addDomain();
+    });
+
+    async function loadDomains() {
+        try {
+            const res = await fetch('/api/site/domains');
+            if (!res.ok) return;
+            const data = await res.json();
+
+            if (data.domains.length === 0) {
+                domainList.innerHTML = '<p class="has-text-grey is-size-7">No custom domains.</p>';
+                return;
+            }
+
+            let html = '<table class="table is-fullwidth is-narrow"><tbody>';
+            for (const d of data.domains) {
+                const name = escapeHtml(d.domain);
+                const status = d.verified_at
+                    ? `<a href="http://${name}/" target="_blank">${name}</a><br><span class="tag is-success is-light">Verified</span>`
+                    : `${name}<br><span class="tag is-warning is-light">Pending</span>
+                       <span class="is-size-7 has-text-grey">serves <code>/.well-known/simple-pages/${escapeHtml(d.token)}</code></span>`;
+                const verify = d.verified_at
+                    ? ''
+                    : `<button class="button is-small is-link is-outlined verify-domain-btn" data-domain="${name}">Verify</button>`;
+                html += `<tr>
+                    <td class="is-size-7">${status}</td>
+                    <td class="has-text-right"><div class="buttons is-right">${verify}
+                        <button class="button is-small is-danger is-outlined remove-domain-btn" data-domain="${name}">Remove</button>
+                    </div></td>
+                </tr>`;
+            }
+            html += '</tbody></table>';
+            domainList.innerHTML = html;
+
+            for (const btn of domainList.querySelectorAll('.verify-domain-btn')) {
+                btn.addEventListener('click', () => verifyDomain(btn));
+            }
+            for (const btn of domainList.querySelectorAll('.remove-domain-btn')) {
+                btn.addEventListener('click', () => removeDomain(btn));
+            }
+        } catch (e) {
+            console.error('Failed to load domains:', e);
+        }
+    }
+
+    async function addDomain() {
+        const domain = domainInput.value.trim();
+        if (!domain) return;
+        domainStatus.textContent = '';
+        try {
+            const res = await fetch('/api/site/domains', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ domain }),
+            });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Failed to add domain');
+            domainInput.value = '';
+            loadDomains();
+        } catch (e) {
+            domainStatus.textContent = e.message;
+        }
+    }
+
+    async function verifyDomain(btn) {
+        btn.classList.add('is-loading');
+        domainStatus.textContent = '';
+        try {
+            const res = await fetch(`/api/site/domains/${encodeURIComponent(btn.dataset.domain)}/verify`, { method: 'POST' });
+            const data = await res.json();
+            if (!res.ok) throw new Error(data.error || 'Verification failed');
+            loadDomains();
+        } catch (e) {
+            domainStatus.textContent = e.message;
+            btn.classList.remove('is-loading');
+        }
+    }
+
+    async function removeDomain(btn) {
+        if (!confirm(`Remove ${btn.dataset.domain}?`)) return;
+        try {
+            await fetch(`/api/site/domains/${encodeURIComponent(btn.dataset.domain)}`, { method: 'DELETE' });
+            loadDomains();
+        } catch (e) {
+            alert('Failed to remove domain');
         }
     }
 
@@ -143,20 +872,7 @@ document.addEventListener('DOMContentLoaded', () => {
             else if (pct > 70) quotaBar.classList.replace('is-info', 'is-warning');
 
             // Update file list
-            const fileList = document.getElementById('file-list');
-            if (data.files.length === 0) {
-                fileList.innerHTML = '<p class="has-text-grey">No files uploaded yet.</p>';
-            } else {
-                let html = '<table class="table is-fullwidth is-narrow"><thead><tr><th>File</th><th>Size</th></tr></thead><tbody>';
-                for (const f of data.files) {
-                    const size = f.size < 1024 ? `${f.size} B`
-                        : f.size < 1048576 ? `${(f.size / 1024).toFixed(1)} KB`
-                        : `${(f.size / 1048576).toFixed(1)} MB`;
-                    html += `<tr><td><code>${f.path}</code></td><td>${size}</td></tr>`;
-                }
-                html += '</tbody></table>';
-                fileList.innerHTML = html;
-            }
+            renderFileTree(data.files);
         } catch (e) {
             console.error('Failed to load site info:', e);
         }